@@ -1,240 +1,740 @@
+use crate::desktop::*;
+use crate::error::*;
+use crate::ini::*;
 use crate::platform::*;
 use crate::types::*;
 use crate::*;
+use log::warn;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
 
-fn check_chrome_profile(profiles_path: &str, profile_name: &str) -> Result<bool> {
-    let mut path = PathBuf::from(profiles_path);
-    path.extend(&[profile_name, "History"]);
-
-    Ok(path.exists() && fs::metadata(path)?.is_file())
+/// Whether a directory in the profiles directory is a profile of the hint
+fn is_profile(hint: &ProfileHint, profiles_path: &Path, dir_name: &str) -> bool {
+    let marker = hint.marker();
+    marker.is_empty() || profiles_path.join(dir_name).join(marker).is_file()
 }
 
-fn check_firefox_profile(profiles_path: &str, profile_name: &str) -> Result<bool> {
+/// Reads profile.info_cache of `Local State` in the Chromium user data directory
+fn read_chrome_metadata(profiles_path: &str) -> Result<HashMap<String, ProfileMetadata>> {
     let mut path = PathBuf::from(profiles_path);
-    path.extend(&[profile_name, "cookies.sqlite"]);
-
-    Ok(path.exists() && fs::metadata(path)?.is_file())
+    path.push("Local State");
+    let local_state: Value = match fs::read_to_string(&path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        contents => serde_json::from_str(&contents?)?,
+    };
+    let mut ret = HashMap::new();
+    let info_cache = local_state["profile"]["info_cache"].as_object();
+    for (dir_name, info) in info_cache.into_iter().flatten() {
+        let text = |key: &str| info[key].as_str().unwrap_or_default().to_string();
+        ret.insert(
+            dir_name.clone(),
+            ProfileMetadata {
+                name: text("name"),
+                email: text("user_name"),
+                avatar: text("avatar_icon"),
+                // is_managed is a number in recent versions and a boolean in
+                // older ones, consumer accounts have the NO_HOSTED_DOMAIN domain
+                managed: info["is_managed"].as_bool().unwrap_or(false)
+                    || info["is_managed"].as_u64().unwrap_or(0) != 0
+                    || !matches!(text("hosted_domain").as_str(), "" | "NO_HOSTED_DOMAIN"),
+            },
+        );
+    }
+    Ok(ret)
 }
 
-fn construct_chrome_profile_arguments(hint: &ProfileHint, name: &str) -> Vec<String> {
-    return vec![format!("{}{}", hint.profile_arg, name)];
+fn chromium_hint(
+    (exe_path, profiles_path): (&str, &str),
+    private_arg: &str,
+    private_name: &str,
+    icon_path: &str,
+) -> ProfileHint {
+    ProfileHint {
+        exe_path: exe_path.to_string(),
+        profiles_path: profiles_path.to_string(),
+        kind: HintKind::Chromium,
+        marker: String::new(),
+        profile_args: vec![],
+        private_arg: private_arg.to_string(),
+        private_name: private_name.to_string(),
+        icon_path: icon_path.to_string(),
+    }
 }
 
-fn construct_firefox_profile_arguments(hint: &ProfileHint, name: &str) -> Vec<String> {
-    return vec![String::from(hint.profile_arg), String::from(name)];
+fn gecko_hint((exe_path, profiles_path): (&str, &str)) -> ProfileHint {
+    ProfileHint {
+        exe_path: exe_path.to_string(),
+        profiles_path: profiles_path.to_string(),
+        kind: HintKind::Gecko,
+        marker: String::new(),
+        profile_args: vec![],
+        private_arg: String::from("-private-window"),
+        private_name: String::from("Private Browsing"),
+        icon_path: String::new(),
+    }
 }
 
-const PROFILE_HINTS: &'static [ProfileHint] = &[
-    ProfileHint {
-        #[cfg(windows)]
-        exe_path: "%programfiles%\\Google\\Chrome\\Application\\chrome.exe",
-        #[cfg(windows)]
-        profiles_path: "%localappdata%\\Google\\Chrome\\User Data",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        exe_path: "google-chrome.desktop",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        profiles_path: "~/.config/google-chrome",
-        #[cfg(target_os = "macos")]
-        exe_path: "Google Chrome.app",
-        #[cfg(target_os = "macos")]
-        profiles_path: "~/Library/Application Support/Google/Chrome",
-
-        private_arg: "--incognito",
-        private_name: "Incognito mode",
-        profile_arg: "--profile-directory=",
-        profile_arg_ctor: construct_chrome_profile_arguments,
-        icon_path: "Google Profile.ico",
-        detector: check_chrome_profile,
-    },
-    ProfileHint {
-        #[cfg(windows)]
-        exe_path: "%programfiles%\\Google\\Chrome Beta\\Application\\chrome.exe",
-        #[cfg(windows)]
-        profiles_path: "%localappdata%\\Google\\Chrome Beta\\User Data",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        exe_path: "google-chrome-beta.desktop",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        profiles_path: "~/.config/google-chrome-beta",
-        #[cfg(target_os = "macos")]
-        exe_path: "Google Chrome Beta.app",
-        #[cfg(target_os = "macos")]
-        profiles_path: "~/Library/Application Support/Google/Chrome Beta",
-
-        private_arg: "--incognito",
-        private_name: "Incognito mode",
-        profile_arg: "--profile-directory=",
-        profile_arg_ctor: construct_chrome_profile_arguments,
-        icon_path: "Google Profile.ico",
-        detector: check_chrome_profile,
-    },
-    ProfileHint {
-        #[cfg(windows)]
-        exe_path: "%programfiles%\\Microsoft\\Edge\\Application\\msedge.exe",
-        #[cfg(windows)]
-        profiles_path: "%localappdata%\\Microsoft\\Edge\\User Data",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        exe_path: "microsoft-edge.desktop",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        profiles_path: "~/.config/microsoft-edge",
-        #[cfg(target_os = "macos")]
-        exe_path: "Microsoft Edge.app",
-        #[cfg(target_os = "macos")]
-        profiles_path: "~/Library/Application Support/Microsoft Edge",
-
-        private_arg: "-inprivate",
-        private_name: "InPrivate mode",
-        profile_arg: "--profile-directory=",
-        profile_arg_ctor: construct_chrome_profile_arguments,
-        icon_path: "Edge Profile.ico",
-        detector: check_chrome_profile,
-    },
-    ProfileHint {
-        #[cfg(windows)]
-        exe_path: "%programfiles%\\Mozilla Firefox\\firefox.exe",
-        #[cfg(windows)]
-        profiles_path: "%appdata%\\Mozilla\\Firefox\\Profiles",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        exe_path: "firefox.desktop",
-        #[cfg(all(unix, not(target_os = "macos")))]
-        profiles_path: "~/.mozilla/firefox",
-        #[cfg(target_os = "macos")]
-        exe_path: "Firefox.app",
-        #[cfg(target_os = "macos")]
-        profiles_path: "~/Library/Application Support/Firefox/Profiles",
-
-        private_arg: "-private-window",
-        private_name: "Private Browsing",
-        profile_arg_ctor: construct_firefox_profile_arguments,
-        profile_arg: "-P",
-        icon_path: "",
-        detector: check_firefox_profile,
-    },
+/// Executable and profiles paths of Chrome, Chrome Beta, Edge and Firefox
+#[cfg(windows)]
+const BROWSER_PATHS: [(&str, &str); 4] = [
+    (
+        "%programfiles%\\Google\\Chrome\\Application\\chrome.exe",
+        "%localappdata%\\Google\\Chrome\\User Data",
+    ),
+    (
+        "%programfiles%\\Google\\Chrome Beta\\Application\\chrome.exe",
+        "%localappdata%\\Google\\Chrome Beta\\User Data",
+    ),
+    (
+        "%programfiles%\\Microsoft\\Edge\\Application\\msedge.exe",
+        "%localappdata%\\Microsoft\\Edge\\User Data",
+    ),
+    (
+        "%programfiles%\\Mozilla Firefox\\firefox.exe",
+        "%appdata%\\Mozilla\\Firefox",
+    ),
 ];
+#[cfg(all(unix, not(target_os = "macos")))]
+const BROWSER_PATHS: [(&str, &str); 4] = [
+    ("google-chrome.desktop", "~/.config/google-chrome"),
+    ("google-chrome-beta.desktop", "~/.config/google-chrome-beta"),
+    ("microsoft-edge.desktop", "~/.config/microsoft-edge"),
+    ("firefox.desktop", "~/.mozilla/firefox"),
+];
+#[cfg(target_os = "macos")]
+const BROWSER_PATHS: [(&str, &str); 4] = [
+    (
+        "Google Chrome.app",
+        "~/Library/Application Support/Google/Chrome",
+    ),
+    (
+        "Google Chrome Beta.app",
+        "~/Library/Application Support/Google/Chrome Beta",
+    ),
+    (
+        "Microsoft Edge.app",
+        "~/Library/Application Support/Microsoft Edge",
+    ),
+    ("Firefox.app", "~/Library/Application Support/Firefox"),
+];
+
+/// Profile hints of the browsers known to bro
+pub fn builtin_profile_hints() -> Vec<ProfileHint> {
+    let [chrome, chrome_beta, edge, firefox] = BROWSER_PATHS;
+    #[allow(unused_mut)]
+    let mut hints = vec![
+        chromium_hint(chrome, "--incognito", "Incognito mode", "Google Profile.ico"),
+        chromium_hint(chrome_beta, "--incognito", "Incognito mode", "Google Profile.ico"),
+        chromium_hint(edge, "-inprivate", "InPrivate mode", "Edge Profile.ico"),
+        gecko_hint(firefox),
+    ];
+    // Linux only: other Chromium and Gecko based browsers
+    #[cfg(all(unix, not(target_os = "macos")))]
+    hints.extend([
+        chromium_hint(
+            ("chromium.desktop", "~/.config/chromium"),
+            "--incognito",
+            "Incognito mode",
+            "",
+        ),
+        chromium_hint(
+            ("chromium-browser.desktop", "~/.config/chromium"),
+            "--incognito",
+            "Incognito mode",
+            "",
+        ),
+        chromium_hint(
+            (
+                "google-chrome-unstable.desktop",
+                "~/.config/google-chrome-unstable",
+            ),
+            "--incognito",
+            "Incognito mode",
+            "Google Profile.ico",
+        ),
+        chromium_hint(
+            (
+                "google-chrome-canary.desktop",
+                "~/.config/google-chrome-canary",
+            ),
+            "--incognito",
+            "Incognito mode",
+            "Google Profile.ico",
+        ),
+        chromium_hint(
+            ("microsoft-edge-beta.desktop", "~/.config/microsoft-edge-beta"),
+            "-inprivate",
+            "InPrivate mode",
+            "Edge Profile.ico",
+        ),
+        chromium_hint(
+            ("microsoft-edge-dev.desktop", "~/.config/microsoft-edge-dev"),
+            "-inprivate",
+            "InPrivate mode",
+            "Edge Profile.ico",
+        ),
+        chromium_hint(
+            (
+                "brave-browser.desktop",
+                "~/.config/BraveSoftware/Brave-Browser",
+            ),
+            "--incognito",
+            "Private window",
+            "",
+        ),
+        chromium_hint(
+            ("vivaldi-stable.desktop", "~/.config/vivaldi"),
+            "--incognito",
+            "Private window",
+            "",
+        ),
+        // Opera on Linux has no profile directories in ~/.config/opera
+        ProfileHint {
+            kind: HintKind::Single,
+            ..chromium_hint(
+                ("opera.desktop", "~/.config/opera"),
+                "--private",
+                "Private window",
+                "",
+            )
+        },
+        chromium_hint(
+            ("thorium-browser.desktop", "~/.config/thorium"),
+            "--incognito",
+            "Incognito mode",
+            "",
+        ),
+        gecko_hint(("librewolf.desktop", "~/.librewolf")),
+        gecko_hint(("waterfox.desktop", "~/.waterfox")),
+        gecko_hint(("floorp.desktop", "~/.floorp")),
+    ]);
+    hints
+}
 
-pub fn get_profiles(browser: &mut Browser) -> Result<()> {
+impl Default for Detection {
+    /// Built-in hints, and ~/Applications where AppImages are usually kept
+    fn default() -> Detection {
+        Detection {
+            hints: builtin_profile_hints(),
+            search_dirs: if cfg!(all(unix, not(target_os = "macos"))) {
+                vec![String::from("~/Applications")]
+            } else {
+                vec![]
+            },
+        }
+    }
+}
+
+/// Hints of the config followed by the built-in hints that they do not
+/// override, a hint overrides built-in hints with the same exe_path
+pub fn merge_profile_hints(hints: &[ProfileHint]) -> Vec<ProfileHint> {
+    let mut ret = hints.to_vec();
+    ret.extend(
+        builtin_profile_hints()
+            .into_iter()
+            .filter(|builtin| !hints.iter().any(|hint| hint.exe_path == builtin.exe_path)),
+    );
+    ret
+}
+
+/// Lists profile directories that contain the marker file of the hint, named
+/// after `Local State` for Chromium based browsers
+fn list_directory_profiles(hint: &ProfileHint, profile_path: &str) -> Result<Vec<Profile>> {
     let mut ret: Vec<Profile> = Vec::new();
-    for hint in PROFILE_HINTS {
-        // only detect profile when startup command(Windows), desktop file name(Linux) matches
-        // and profiles directory exists
-        let detected_path = detect_path(&browser, &hint)?;
-        if detected_path.is_empty() || hint.profiles_path.is_empty() {
+    let metadata = match hint.kind {
+        HintKind::Chromium => read_chrome_metadata(profile_path).unwrap_or_else(|e| {
+            warn!("Cannot read profile names in {}: {:#}", profile_path, e);
+            HashMap::new()
+        }),
+        _ => HashMap::new(),
+    };
+    let dirs = std::fs::read_dir(profile_path)?;
+    for dir in dirs {
+        let dir_entry = dir?;
+        let dir_name = dir_entry.file_name();
+        let Some(dir_name_string) = dir_name.to_str().map(str::to_string) else {
+            warn!("Skipping profile directory {:?}, its name is not UTF-8", dir_name);
+            continue;
+        };
+        // process only directory
+        if !dir_entry.metadata()?.is_dir()
+            || !is_profile(hint, Path::new(profile_path), &dir_name_string)
+        {
             continue;
         }
-        // change browser command to path of executable
-        browser.command = detected_path;
-        let profile_paths = expand_path(hint.profiles_path)?;
-        assert!(profile_paths.len() == 1);
+        let mut dir_path = PathBuf::from(profile_path);
+        dir_path.push(&dir_name_string);
+        let mut icon_path = String::new();
+        if !hint.icon_path.is_empty() {
+            let mut path = PathBuf::from(profile_path);
+            path.extend(&[dir_name_string.clone(), hint.icon_path.to_string()]);
+            // println!("{:?}", path);
+            if path.exists() {
+                icon_path = path.to_string_lossy().to_string();
+            }
+        }
+        let metadata = metadata.get(&dir_name_string).cloned().unwrap_or_default();
+        let name = if metadata.name.is_empty() {
+            dir_name_string.clone()
+        } else {
+            metadata.name
+        };
+        let path = dir_path.to_string_lossy().to_string();
         ret.push(Profile {
-            id: String::from("__PRIVATE__"),
-            name: String::from(hint.private_name),
-            args: vec![String::from(hint.private_arg)],
-            icon_path: String::new(),
+            id: dir_name_string.clone(),
+            args: hint.construct_profile_arguments(&dir_name_string, &name, &path),
+            name,
+            icon_path,
+            path,
+            email: metadata.email,
+            avatar: metadata.avatar,
+            managed: metadata.managed,
+            ..Profile::default()
         });
-        let profile_path = &profile_paths[0];
-        let dirs = std::fs::read_dir(profile_path)?;
-        for dir in dirs {
-            let dir_entry = dir?;
-            let dir_name = dir_entry.file_name();
-            let dir_name_string = dir_name.clone().into_string().unwrap();
-            // process only directory
-            if !dir_entry.metadata()?.is_dir()
-                || !(hint.detector)(profile_path, dir_name.to_str().unwrap())?
+    }
+    Ok(ret)
+}
+
+/// Lists the profiles directory as the one profile of the browser when it
+/// contains the marker file
+fn list_single_profile(hint: &ProfileHint, profile_path: &str) -> Vec<Profile> {
+    let path = Path::new(profile_path);
+    let marker = hint.marker();
+    if !marker.is_empty() && !path.join(marker).is_file() {
+        return vec![];
+    }
+    let id = String::from("Default");
+    vec![Profile {
+        args: hint.construct_profile_arguments(&id, &id, profile_path),
+        name: id.clone(),
+        id,
+        path: profile_path.to_string(),
+        default: true,
+        ..Profile::default()
+    }]
+}
+
+/// Lists profiles registered in profiles.ini of the Firefox root directory,
+/// which are launched by name, then profile directories that are not
+/// registered, which are launched by path. The default profile of the
+/// install in installs.ini comes first
+fn list_firefox_profiles(hint: &ProfileHint, root: &str) -> Result<Vec<Profile>> {
+    let root = PathBuf::from(root);
+    let profiles_ini = read_ini(&root.join("profiles.ini"))?;
+    let installs_ini = read_ini(&root.join("installs.ini"))?;
+
+    let mut registered: Vec<(&str, PathBuf, bool)> = Vec::new();
+    for section in profiles_ini.iter().filter(|s| s.name.starts_with("Profile")) {
+        let (Some(name), Some(path)) = (section.get("Name"), section.get("Path")) else {
+            continue;
+        };
+        let dir_path = if section.get("IsRelative") == Some("1") {
+            root.join(path)
+        } else {
+            PathBuf::from(path)
+        };
+        registered.push((name, dir_path, section.get("Default") == Some("1")));
+    }
+
+    // [Install...] sections of profiles.ini duplicate installs.ini in newer
+    // versions. Installs are keyed by a hash of their directory, and several
+    // installs, like release and ESR, can share the root directory, so the
+    // default of an install only counts when its profile is registered, and
+    // the one that is also the legacy default wins among those
+    let install_defaults: Vec<PathBuf> = installs_ini
+        .iter()
+        .chain(profiles_ini.iter().filter(|s| s.name.starts_with("Install")))
+        .filter_map(|section| section.get("Default"))
+        .map(|path| root.join(path))
+        .filter(|path| registered.iter().any(|(_, dir_path, _)| dir_path == path))
+        .collect();
+    let install_default = install_defaults
+        .iter()
+        .find(|path| {
+            registered
+                .iter()
+                .any(|(_, dir_path, legacy)| *legacy && dir_path == *path)
+        })
+        .or(install_defaults.first());
+
+    let mut ret: Vec<Profile> = Vec::new();
+    for (name, dir_path, legacy_default) in registered {
+        let default = match install_default {
+            Some(install_default) => *install_default == dir_path,
+            None => legacy_default,
+        };
+        let path = dir_path.to_string_lossy().to_string();
+        ret.push(Profile {
+            id: name.to_string(),
+            name: name.to_string(),
+            args: hint.construct_profile_arguments(name, name, &path),
+            path,
+            default,
+            ..Profile::default()
+        });
+    }
+
+    // profiles created with --profile or left behind by a removed profiles.ini
+    for dir in [root.clone(), root.join("Profiles")] {
+        let entries = match fs::read_dir(&dir) {
+            Err(_) => continue,
+            entries => entries?,
+        };
+        for entry in entries {
+            let dir_path = entry?.path();
+            let dir_name = dir_path.file_name().unwrap_or_default().to_string_lossy();
+            let path = dir_path.to_string_lossy().to_string();
+            if !dir_path.is_dir()
+                || !is_profile(hint, &dir, &dir_name)
+                || ret.iter().any(|profile| Path::new(&profile.path) == dir_path)
             {
                 continue;
             }
-            let mut icon_path = String::new();
-            if !hint.icon_path.is_empty() {
-                let mut path = PathBuf::from(profile_path);
-                path.extend(&[dir_name_string.clone(), hint.icon_path.to_string()]);
-                // println!("{:?}", path);
-                if path.exists() {
-                    icon_path = path.to_str().unwrap().to_string();
-                }
-            }
             ret.push(Profile {
-                id: dir_name_string.clone(),
-                name: dir_name_string.clone(),
-                args: hint.construct_profile_arguments(&dir_name_string),
-                icon_path: icon_path,
+                id: dir_name.to_string(),
+                name: dir_name.to_string(),
+                args: vec![String::from("--profile"), path.clone()],
+                path,
+                ..Profile::default()
             });
         }
-        // println!("{} {:?} {:?} {:?}", browser.command, paths, browser, ret);
-        browser.profiles = ret;
-        return Ok(());
+    }
+
+    ret.sort_by_key(|profile| !profile.default);
+
+    // containers of a profile follow the profile
+    let mut with_containers: Vec<Profile> = Vec::new();
+    for profile in ret {
+        let containers = read_firefox_containers(Path::new(&profile.path)).unwrap_or_else(|e| {
+            warn!("Cannot read containers of {}: {:#}", profile.path, e);
+            vec![]
+        });
+        let parent = profile.clone();
+        with_containers.push(profile);
+        for container in containers {
+            with_containers.push(Profile {
+                id: format!("{}/{}", parent.id, container),
+                name: format!("{} ({})", container, parent.name),
+                args: parent.args.clone(),
+                path: parent.path.clone(),
+                container,
+                ..Profile::default()
+            });
+        }
+    }
+    Ok(with_containers)
+}
+
+/// Names of the containers in containers.json of a Firefox profile, built-in
+/// containers only have a localization id instead of a name
+fn read_firefox_containers(profile_path: &Path) -> Result<Vec<String>> {
+    let containers: Value = match fs::read_to_string(profile_path.join("containers.json")) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        contents => serde_json::from_str(&contents?)?,
+    };
+    let mut ret: Vec<String> = Vec::new();
+    let identities = containers["identities"].as_array();
+    for identity in identities.into_iter().flatten() {
+        // private identities are used internally, e.g. for thumbnails
+        if !identity["public"].as_bool().unwrap_or(false) {
+            continue;
+        }
+        let name = match (identity["name"].as_str(), identity["l10nID"].as_str()) {
+            (Some(name), _) => name,
+            (None, Some("userContextPersonal.label")) => "Personal",
+            (None, Some("userContextWork.label")) => "Work",
+            (None, Some("userContextBanking.label")) => "Banking",
+            (None, Some("userContextShopping.label")) => "Shopping",
+            _ => continue,
+        };
+        ret.push(name.to_string());
+    }
+    Ok(ret)
+}
+
+/// URL that the Open URL in Container extension opens in the container
+fn container_uri(container: &str, uri: &str) -> String {
+    format!(
+        "ext+container:name={}&url={}",
+        utf8_percent_encode(container, NON_ALPHANUMERIC),
+        utf8_percent_encode(uri, NON_ALPHANUMERIC)
+    )
+}
+
+/// Detects profiles of a browser with the first hint that matches it
+pub fn get_profiles(browser: &mut Browser, hints: &[ProfileHint]) -> Result<()> {
+    for hint in hints {
+        // only detect profile when startup command(Windows), desktop file name(Linux) matches
+        // and profiles directory exists
+        let detected_path = detect_path(browser, hint)?;
+        if detected_path.is_empty() || hint.profiles_path.is_empty() {
+            continue;
+        }
+        // change browser command to path of executable
+        browser.command = detected_path;
+        return get_hint_profiles(browser, hint);
     }
     Ok(())
 }
 
-pub fn launch_browser_command(
-    browsers: &Vec<Browser>,
-    browser_spec: &String,
-    uri: &str,
-) -> Result<(String, Vec<String>)> {
-    let vec: Vec<&str> = browser_spec.split(":").collect();
-    if vec.len() > 2 {
+/// Detects profiles of a browser with a hint that is known to match it
+pub fn get_hint_profiles(browser: &mut Browser, hint: &ProfileHint) -> Result<()> {
+    let profile_paths = expand_path(&hint.profiles_path)?;
+    if profile_paths.len() != 1 {
         return Err(Error::msg(format!(
-            "Unkown browser specification {}",
-            browser_spec
+            "profiles path {} expands to {} paths",
+            hint.profiles_path,
+            profile_paths.len()
         )));
     }
+    let mut ret = Vec::new();
+    if !hint.private_arg.is_empty() {
+        ret.push(Profile {
+            id: String::from("__PRIVATE__"),
+            name: match hint.private_name.as_str() {
+                "" => String::from("Private window"),
+                name => name.to_string(),
+            },
+            args: vec![hint.private_arg.clone()],
+            ..Profile::default()
+        });
+    }
+    // profiles directory does not exist before the browser is run for the first time
+    if Path::new(&profile_paths[0]).is_dir() {
+        ret.extend(match hint.kind {
+            HintKind::Gecko => list_firefox_profiles(hint, &profile_paths[0])?,
+            HintKind::Single => list_single_profile(hint, &profile_paths[0]),
+            _ => list_directory_profiles(hint, &profile_paths[0])?,
+        });
+    }
+    // println!("{} {:?} {:?} {:?}", browser.command, paths, browser, ret);
+    browser.profiles = ret;
+    Ok(())
+}
 
-    for browser in browsers {
-        if browser.id != vec[0] {
-            continue;
+/// Detects profiles of every browser at once, one thread per browser. A
+/// browser whose detection fails or panics is kept without profiles and
+/// reported in the warnings
+pub fn detect_profiles_parallel<F>(browsers: &mut [Browser], detect: F) -> Vec<DetectionWarning>
+where
+    F: Fn(&mut Browser) -> Result<()> + Sync,
+{
+    let detect = &detect;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = browsers
+            .iter_mut()
+            .map(|browser| {
+                let id = browser.id.clone();
+                let handle = scope.spawn(move || {
+                    let result = detect(browser);
+                    if result.is_err() {
+                        browser.profiles.clear();
+                    }
+                    result
+                });
+                (id, handle)
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|(id, handle)| {
+                // the panic message has been printed by the panic hook already
+                let message = match handle.join() {
+                    Err(_) => String::from("profile detection panicked"),
+                    result => format!("cannot detect profiles: {:#}", result.ok()?.err()?),
+                };
+                Some(DetectionWarning {
+                    source: id,
+                    message,
+                })
+            })
+            .collect()
+    })
+}
+
+/// Browser specification of a browser or one of its profiles, as accepted by
+/// launch_browser_command
+pub fn browser_spec(browser: &Browser, profile: Option<&Profile>) -> String {
+    match profile {
+        Some(profile) => format!("{}:{}", browser.id, profile.id),
+        None => browser.id.clone(),
+    }
+}
+
+/// Browser specification of the private browsing profile of the browser of a
+/// specification
+pub fn private_spec(browser_spec: &str) -> String {
+    let browser_id = browser_spec.split(':').next().unwrap_or_default();
+    format!("{}:__PRIVATE__", browser_id)
+}
+
+/// Finds a profile by its id, by the email of its signed-in account with
+/// `@alice@example.com` or by its name with `name=Work`. Emails and names are
+/// read again from `Local State`, as they may have changed since detection
+pub fn find_profile<'a>(browser: &'a Browser, selector: &str) -> Option<&'a Profile> {
+    let (email, name) = match (selector.strip_prefix('@'), selector.strip_prefix("name=")) {
+        (Some(email), _) => (Some(email), None),
+        (_, Some(name)) => (None, Some(name)),
+        _ => return browser.profiles.iter().find(|profile| profile.id == selector),
+    };
+    let metadata = browser
+        .profiles
+        .iter()
+        .find_map(|profile| Path::new(&profile.path).parent())
+        .and_then(|dir| read_chrome_metadata(&dir.to_string_lossy()).ok())
+        .unwrap_or_default();
+    browser.profiles.iter().find(|profile| {
+        let (profile_email, profile_name) = match metadata.get(&profile.id) {
+            Some(metadata) => (&metadata.email, &metadata.name),
+            None => (&profile.email, &profile.name),
+        };
+        email.is_some_and(|email| !email.is_empty() && profile_email.eq_ignore_ascii_case(email))
+            || name.is_some_and(|name| profile_name == name)
+    })
+}
+
+/// Finds the browser and the profile of a browser specification, the profile
+/// is None when the specification has no profile
+fn find_spec<'a>(
+    browsers: &'a [Browser],
+    browser_spec: &str,
+) -> Result<(&'a Browser, Option<&'a Profile>)> {
+    // profile selectors like @alice@example.com may contain colons
+    let vec: Vec<&str> = browser_spec.splitn(2, ':').collect();
+    if vec[0].is_empty() {
+        return Err(BroError::InvalidSpec(browser_spec.to_string()).into());
+    }
+    let Some(browser) = browsers.iter().find(|browser| browser.id == vec[0]) else {
+        return Err(BroError::UnknownBrowser(vec[0].to_string()).into());
+    };
+    // use browser itself with no profile
+    if vec.len() == 1 {
+        return Ok((browser, None));
+    }
+    match find_profile(browser, vec[1]) {
+        Some(profile) => Ok((browser, Some(profile))),
+        // when no profile is found
+        None => Err(BroError::UnknownProfile {
+            browser: vec[0].to_string(),
+            profile: vec[1].to_string(),
         }
-        // use browser itself with no profile
-        if vec.len() == 1 {
-            #[cfg(target_os = "macos")]
-            return Ok((
-                String::from("open"),
-                vec![
+        .into()),
+    }
+}
+
+/// URLs as they are passed to the browser, wrapped for the container of the
+/// profile if it has one
+fn profile_uris(profile: Option<&Profile>, uris: &[String]) -> Vec<String> {
+    uris.iter()
+        .map(|uri| match profile.map_or("", |profile| profile.container.as_str()) {
+            "" => uri.clone(),
+            container => container_uri(container, uri),
+        })
+        .collect()
+}
+
+/// URLs that launch_browser_command passes to the browser, the URLs
+/// themselves when the browser specification cannot be resolved
+pub fn launched_uris(browsers: &[Browser], browser_spec: &str, uris: &[String]) -> Vec<String> {
+    find_spec(browsers, browser_spec).map_or_else(
+        |_| uris.to_vec(),
+        |(_, profile)| profile_uris(profile, uris),
+    )
+}
+
+/// Constructs the commands that open all URLs, one command for all of them
+/// unless the browser takes one URL per launch
+pub fn launch_browser_command(
+    browsers: &[Browser],
+    browser_spec: &str,
+    uris: &[String],
+) -> Result<Vec<(String, Vec<String>)>> {
+    let (browser, profile) = find_spec(browsers, browser_spec)?;
+    let uris = profile_uris(profile, uris);
+    // matched profile, its arguments go before the URLs
+    let profile_args: &[String] = profile.map_or(&[], |profile| &profile.args);
+
+    // construct arguments
+    let launches = expand_exec_urls(&browser.args, profile_args, &uris);
+
+    #[cfg(target_os = "macos")]
+    {
+        Ok(launches
+            .into_iter()
+            .map(|args| {
+                let mut vec = vec![
                     String::from("-n"),      // launch a new instance
                     String::from("-a"),      // using the application
                     browser.command.clone(), // application name
                     String::from("--args"),  // pass arguments
-                    String::from(uri),       // uri
-                ],
-            ));
-            #[cfg(not(target_os = "macos"))]
-            return Ok((
-                String::from(browser.command.clone()),
-                vec![String::from(uri)],
-            ));
-        }
+                ];
+                vec.extend(args);
+                (String::from("open"), vec)
+            })
+            .collect())
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        Ok(launches
+            .into_iter()
+            .map(|args| (browser.command.clone(), args))
+            .collect())
+    }
+}
 
-        for profile in &browser.profiles {
-            if profile.id != vec[1] {
-                continue;
-            }
+/// Checks whether the program of a browser command can still be found,
+/// either as a path or through the PATH environment variable. The command is
+/// checked as the program that is spawned, arguments are in Browser::args.
+/// On macOS the command is the path of the application bundle
+pub fn command_exists(command: &str) -> bool {
+    if command.is_empty() {
+        return false;
+    }
+    let path = Path::new(command);
+    if path.is_absolute() || path.components().count() > 1 {
+        return path.exists();
+    }
+    match env::var_os("PATH") {
+        Some(paths) => env::split_paths(&paths).any(|dir| {
+            let candidate = dir.join(command);
+            candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+        }),
+        None => false,
+    }
+}
 
-            // construct arguments
+/// Re-runs detection of a browser whose command no longer exists and replaces
+/// the stale entry in memory, returns the fresh entry when it has been
+/// replaced. The stale entry is kept when detection fails
+pub fn refresh_stale_browser<'a>(
+    browsers: &'a mut [Browser],
+    id: &str,
+    detection: &Detection,
+) -> Option<&'a Browser> {
+    let stale = browsers
+        .iter()
+        .position(|b| b.id == id && !command_exists(&b.command))?;
+    warn!(
+        "Command {} of browser {} does not exist, re-detecting",
+        browsers[stale].command, id
+    );
 
-            #[cfg(target_os = "macos")]
-            {
-                let mut vec = vec![
-                    String::from("-n"),
-                    String::from("-a"),
-                    browser.command.clone(),
-                    String::from("--args"),
-                ];
-                vec.extend(profile.args.clone());
-                vec.push(uri.to_string());
-                return Ok((String::from("open"), vec));
+    match detect_browser(id, detection) {
+        Err(e) => {
+            warn!("Cannot re-detect browser {}: {:#}", id, e);
+            None
+        }
+        detected => match detected.unwrap() {
+            Some(fresh) if command_exists(&fresh.command) => {
+                browsers[stale] = fresh;
+                Some(&browsers[stale])
             }
-            #[cfg(not(target_os = "macos"))]
-            {
-                let mut vec = profile.args.clone();
-                vec.push(String::from(uri));
-                // matched profile
-                return Ok((String::from(browser.command.clone()), vec));
+            _ => {
+                warn!("Browser {} is not detected anymore", id);
+                None
             }
-        }
-
-        // when no profile is found
-        return Err(Error::msg(format!(
-            "Unknown profile {} for browser {}",
-            vec[1], vec[0]
-        )));
+        },
     }
-    return Err(Error::msg(format!("Unkown browser {}", vec[0])));
 }
@@ -0,0 +1,308 @@
+use crate::error::*;
+use crate::browser::*;
+use crate::platform::*;
+use crate::rule::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::*;
+use anyhow::Context;
+use log::warn;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::time::SystemTime;
+use std::path::Path;
+
+/// Configs of older versions keep the whole Exec line of a desktop entry in
+/// the command of browsers without a hint, like `epiphany %U`, while the
+/// command is launched as the program itself. Such commands are split into
+/// the program and its args
+#[cfg(all(unix, not(target_os = "macos")))]
+fn split_exec_commands(browsers: &mut [Browser], fix: &str) {
+    for browser in browsers
+        .iter_mut()
+        .filter(|b| b.args.is_empty() && !Path::new(&b.command).exists())
+    {
+        let keys = crate::desktop::DesktopEntryKeys {
+            name: &browser.name,
+            ..Default::default()
+        };
+        let (command, args) = match desktop_entry_command(&browser.command, &keys) {
+            Err(e) => {
+                warn!(
+                    "Cannot split command {} of browser {}: {:#}",
+                    browser.command, browser.id, e
+                );
+                continue;
+            }
+            split => split.unwrap(),
+        };
+        if args.is_empty() && command == browser.command {
+            continue;
+        }
+        warn!(
+            "Command {} of browser {} includes its arguments, {}",
+            browser.command, browser.id, fix
+        );
+        browser.command = command;
+        browser.args = args;
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let contents = match fs::read_to_string(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(BroError::ConfigNotFound(path.to_path_buf()).into())
+            }
+            other => other?,
+        };
+        let mut config: Config =
+            serde_json::from_str(&contents).map_err(|e| BroError::InvalidConfig {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            })?;
+        if config.detected_browsers.is_empty() && config.custom_browsers.is_empty() {
+            return Err(BroError::InvalidConfig {
+                path: path.to_path_buf(),
+                reason: String::from("no browsers are defined"),
+            }
+            .into());
+        }
+        if config.rules.is_empty() {
+            return Err(BroError::InvalidConfig {
+                path: path.to_path_buf(),
+                reason: String::from("no rules are defined"),
+            }
+            .into());
+        }
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            split_exec_commands(&mut config.detected_browsers, "run `bro config --refresh`");
+            split_exec_commands(&mut config.custom_browsers, "move them to its args");
+        }
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomically(path, json.as_bytes())
+    }
+
+    pub fn example() -> Result<Config> {
+        let detected = available_browsers(&Detection::default())?;
+        for warning in &detected.warnings {
+            warn!("{}: {}", warning.source, warning.message);
+        }
+        Ok(Config {
+            detected_browsers: detected.browsers,
+            custom_browsers: vec![],
+            rules: vec![
+                Rule::new("DOMAIN-SUFFIX", "contoso.com", "Google Chrome:Profile 1"),
+                Rule::new("FINAL", "", "Google Chrome:Default"),
+            ],
+            match_strategy: MatchStrategy::default(),
+            log: LogConfig::default(),
+            history: HistoryConfig::default(),
+            modes: BTreeMap::new(),
+            profile_hints: vec![],
+            search_dirs: None,
+        })
+    }
+
+    /// All browsers usable by rules, custom browsers are added at back
+    pub fn browsers(&self) -> Vec<Browser> {
+        let mut browsers = self.detected_browsers.clone();
+        browsers.extend(self.custom_browsers.clone());
+        browsers
+    }
+
+    /// Replaces detected browsers with a fresh detection, keeping everything
+    /// else, returns the warnings of the detection
+    pub fn refresh_detected_browsers(&mut self) -> Result<Vec<DetectionWarning>> {
+        let detected = available_browsers(&self.detection())?;
+        self.detected_browsers = detected.browsers;
+        Ok(detected.warnings)
+    }
+
+    /// Profile hints of the config merged with the built-in hints, and the
+    /// search directories of the config
+    pub fn detection(&self) -> Detection {
+        let default = Detection::default();
+        Detection {
+            hints: merge_profile_hints(&self.profile_hints),
+            search_dirs: self.search_dirs.clone().unwrap_or(default.search_dirs),
+        }
+    }
+
+    fn check_rule_index(&self, index: usize) -> Result<()> {
+        if index >= self.rules.len() {
+            return Err(BroError::UnknownRule(index).into());
+        }
+        Ok(())
+    }
+
+    /// Compiles the rule and resolves its browser specification against
+    /// the known browsers
+    fn check_rule_of(&self, rules: &[Rule], index: usize) -> Result<()> {
+        let rule = &rules[index];
+        compile_rule(index, rule)?;
+        let uris = [String::from("https://example.com/")];
+        launch_browser_command(&self.browsers(), &rule.browser, &uris)
+            .with_context(|| format!("rule #{} `{}`", index, rule))?;
+        Ok(())
+    }
+
+    pub fn check_rule(&self, index: usize) -> Result<()> {
+        self.check_rule_index(index)?;
+        self.check_rule_of(&self.rules, index)
+    }
+
+    pub fn mode_rules(&self, mode: &str) -> Result<&Vec<Rule>> {
+        self.modes
+            .get(mode)
+            .ok_or_else(|| BroError::UnknownMode(mode.to_string()).into())
+    }
+
+    /// Checks all enabled rules of a mode like check_rule
+    pub fn check_mode(&self, mode: &str) -> Result<()> {
+        let rules = self.mode_rules(mode)?;
+        for (index, rule) in rules.iter().enumerate() {
+            if !rule.disabled {
+                self.check_rule_of(rules, index)
+                    .with_context(|| format!("mode {}", mode))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles rules of the active mode followed by the base rules, a mode
+    /// that has been removed from the config is ignored. FINAL rules of the
+    /// mode go right before the base FINAL rule, so that they replace it
+    /// instead of shadowing all base rules
+    pub fn compile_active_rules(&self, mode: Option<&str>) -> Result<Vec<CompiledRule>> {
+        let mut ret: Vec<CompiledRule> = Vec::new();
+        let mut mode_finals: Vec<CompiledRule> = Vec::new();
+        match mode.map(|mode| (mode, self.modes.get(mode))) {
+            Some((mode, Some(rules))) => {
+                let compiled = compile_rules(rules).with_context(|| format!("mode {}", mode))?;
+                for rule in compiled {
+                    let rule = CompiledRule {
+                        mode: Some(mode.to_string()),
+                        ..rule
+                    };
+                    if rule.rule.matcher == "FINAL" {
+                        mode_finals.push(rule);
+                    } else {
+                        ret.push(rule);
+                    }
+                }
+            }
+            Some((mode, None)) => warn!(
+                "Mode {} is not defined in the config, run `bro mode clear` to use the base rules",
+                mode
+            ),
+            None => (),
+        }
+        let mut base = compile_rules(&self.rules)?;
+        let base_final = base
+            .iter()
+            .position(|rule| rule.rule.matcher == "FINAL")
+            .unwrap_or(base.len());
+        base.splice(base_final..base_final, mode_finals);
+        ret.extend(base);
+        Ok(ret)
+    }
+
+    /// Index of the first enabled FINAL rule, rules after it are never matched
+    pub fn final_rule_index(&self) -> Option<usize> {
+        self.rules
+            .iter()
+            .position(|rule| rule.matcher == "FINAL" && !rule.disabled)
+    }
+
+    /// Inserts a rule at the index, or before the FINAL rule when no index is
+    /// given, and returns its index
+    pub fn insert_rule(&mut self, at: Option<usize>, rule: Rule) -> Result<usize> {
+        let index = at
+            .or_else(|| self.final_rule_index())
+            .unwrap_or(self.rules.len());
+        if index > self.rules.len() {
+            return Err(BroError::UnknownRule(index).into());
+        }
+        self.rules.insert(index, rule);
+        self.check_rule(index)?;
+        // a rule that has expired already would only wait to be pruned
+        if self.rules[index].is_expired_at(SystemTime::now())? {
+            let rule = self.rules.remove(index);
+            return Err(BroError::InvalidRule {
+                index,
+                reason: format!(
+                    "expires_at {} is in the past",
+                    rule.expires_at.as_deref().unwrap_or_default()
+                ),
+                rule,
+            }
+            .into());
+        }
+        Ok(index)
+    }
+
+    pub fn move_rule(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check_rule_index(from)?;
+        self.check_rule_index(to)?;
+        let rule = self.rules.remove(from);
+        self.rules.insert(to, rule);
+        Ok(())
+    }
+
+    pub fn remove_rule(&mut self, index: usize) -> Result<Rule> {
+        self.check_rule_index(index)?;
+        if self.rules.len() == 1 {
+            return Err(Error::msg("the only rule cannot be removed"));
+        }
+        Ok(self.rules.remove(index))
+    }
+
+    /// Enabled rules are checked as they will be matched again
+    pub fn set_rule_disabled(&mut self, index: usize, disabled: bool) -> Result<()> {
+        self.check_rule_index(index)?;
+        self.rules[index].disabled = disabled;
+        if !disabled {
+            self.check_rule(index)?;
+        }
+        Ok(())
+    }
+
+    /// Expired rules of the base rules (mode None) and of all modes, with their indices
+    pub fn expired_rules(&self, time: SystemTime) -> Vec<(Option<&str>, usize, &Rule)> {
+        let groups = std::iter::once((None, &self.rules))
+            .chain(self.modes.iter().map(|(mode, rules)| (Some(mode.as_str()), rules)));
+        let mut ret = Vec::new();
+        for (mode, rules) in groups {
+            for (index, rule) in rules.iter().enumerate() {
+                // rules with invalid times are reported by `bro check` instead
+                if rule.is_expired_at(time).unwrap_or(false) {
+                    ret.push((mode, index, rule));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Removes expired rules from the base rules and all modes, and returns
+    /// how many have been removed
+    pub fn remove_expired_rules(&mut self, time: SystemTime) -> Result<usize> {
+        let is_expired = |rule: &Rule| rule.is_expired_at(time).unwrap_or(false);
+        if self.rules.iter().all(is_expired) {
+            return Err(Error::msg(
+                "all rules have expired, at least one rule has to be kept",
+            ));
+        }
+        let count = self.expired_rules(time).len();
+        self.rules.retain(|rule| !is_expired(rule));
+        for rules in self.modes.values_mut() {
+            rules.retain(|rule| !is_expired(rule));
+        }
+        Ok(count)
+    }
+}
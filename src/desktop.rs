@@ -0,0 +1,239 @@
+//! Exec values of desktop entries, following the Desktop Entry Specification.
+//! Browser commands keep the URL field codes of Exec in their arguments, so
+//! that URLs are inserted where the desktop entry expects them
+use crate::{Error, Result};
+use percent_encoding::percent_decode_str;
+
+/// Keys of a desktop entry that field codes of its Exec value expand to
+#[derive(Debug, Default, Clone)]
+pub struct DesktopEntryKeys<'a> {
+    /// Name, for %c
+    pub name: &'a str,
+    /// Icon, for %i
+    pub icon: &'a str,
+    /// Path of the desktop file, for %k
+    pub path: &'a str,
+}
+
+/// Removes the escapes of string values: \s, \n, \t, \r and \\
+pub fn unescape_value(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => ret.push(' '),
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some('r') => ret.push('\r'),
+            Some('\\') => ret.push('\\'),
+            // other escapes are left for the quoting rules of Exec
+            Some(other) => {
+                ret.push('\\');
+                ret.push(other);
+            }
+            None => ret.push('\\'),
+        }
+    }
+    ret
+}
+
+/// Splits an unescaped Exec value into the program and its arguments.
+/// Arguments are separated by spaces and quoted with double quotes, inside
+/// which `"`, `` ` ``, `$` and `\` are escaped with a backslash. Single quotes
+/// and backslashes outside quotes, which many desktop files use although the
+/// specification does not allow them, are handled like a POSIX shell does
+pub fn split_exec(exec: &str) -> Result<Vec<String>> {
+    let mut ret: Vec<String> = Vec::new();
+    // None between arguments, so that "" is kept as an empty argument
+    let mut arg: Option<String> = None;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => ret.extend(arg.take()),
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '`' | '$' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => break,
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(Error::msg(format!("unterminated quote in {}", exec))),
+                    }
+                }
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(Error::msg(format!("unterminated quote in {}", exec))),
+                    }
+                }
+            }
+            '\\' => {
+                let arg = arg.get_or_insert_with(String::new);
+                arg.extend(chars.next());
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    ret.extend(arg);
+    if ret.is_empty() {
+        return Err(Error::msg("Exec is empty"));
+    }
+    Ok(ret)
+}
+
+/// Replaces every field code of an argument with what expand appends to the
+/// result for its letter
+fn replace_field_codes(arg: &str, mut expand: impl FnMut(char, &mut String)) -> String {
+    let mut ret = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(code) => expand(code, &mut ret),
+            None => ret.push('%'),
+        }
+    }
+    ret
+}
+
+fn is_url_field_code(arg: &str) -> bool {
+    matches!(arg, "%f" | "%F" | "%u" | "%U")
+}
+
+/// Parses the Exec value of a desktop entry into the program and its
+/// arguments. %i, %c and %k are expanded and deprecated field codes are
+/// removed, while %% and the URL field codes %f, %F, %u and %U are kept for
+/// expand_exec_urls
+pub fn parse_exec(value: &str, keys: &DesktopEntryKeys) -> Result<Vec<String>> {
+    let mut ret: Vec<String> = Vec::new();
+    for arg in split_exec(&unescape_value(value))? {
+        match arg.as_str() {
+            "%i" if keys.icon.is_empty() => (),
+            "%i" => ret.extend([String::from("--icon"), keys.icon.to_string()]),
+            // a field code that expands to nothing removes its argument
+            "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => (),
+            arg => ret.push(replace_field_codes(arg, |code, out| match code {
+                '%' | 'f' | 'F' | 'u' | 'U' => {
+                    out.push('%');
+                    out.push(code);
+                }
+                'c' => out.push_str(keys.name),
+                'k' => out.push_str(keys.path),
+                _ => (),
+            })),
+        }
+    }
+    if ret.is_empty() {
+        return Err(Error::msg(format!("Exec {} has no program", value)));
+    }
+    Ok(ret)
+}
+
+/// Local path of a file URL for %f and %F, other URLs are passed unchanged
+fn local_path(url: &str) -> String {
+    match url.strip_prefix("file://") {
+        Some(path) => percent_decode_str(path).decode_utf8_lossy().to_string(),
+        None => url.to_string(),
+    }
+}
+
+/// Arguments after which a browser only takes URLs, profile arguments go
+/// before them
+const END_OF_OPTIONS: &[&str] = &["--", "--untrusted-args", "--single-argument"];
+
+/// URL field code of an argument, also when it is embedded like --app=%u
+fn url_field_code(arg: &str) -> Option<char> {
+    let mut ret = None;
+    replace_field_codes(arg, |code, _| {
+        if matches!(code, 'f' | 'F' | 'u' | 'U') {
+            ret.get_or_insert(code);
+        }
+    });
+    ret
+}
+
+/// Arguments of every launch of a browser command, with the profile
+/// arguments and the URLs inserted at its URL field code, or appended when it
+/// has none. %u and %f take one URL, so the browser is launched once per URL,
+/// while %U, %F and commands without a field code take all URLs at once
+pub fn expand_exec_urls(
+    args: &[String],
+    profile_args: &[String],
+    urls: &[String],
+) -> Vec<Vec<String>> {
+    let single = args
+        .iter()
+        .any(|arg| matches!(url_field_code(arg), Some('u' | 'f')));
+    if single && urls.len() > 1 {
+        urls.iter()
+            .map(|url| expand_launch_args(args, profile_args, std::slice::from_ref(url)))
+            .collect()
+    } else {
+        vec![expand_launch_args(args, profile_args, urls)]
+    }
+}
+
+/// Arguments of one launch of a browser command, see expand_exec_urls
+fn expand_launch_args(args: &[String], profile_args: &[String], urls: &[String]) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    let mut inserted = false;
+    let insert_profile_args = |ret: &mut Vec<String>| {
+        let end_of_options = ret
+            .iter()
+            .rev()
+            .take_while(|arg| END_OF_OPTIONS.contains(&arg.as_str()))
+            .count();
+        let at = ret.len() - end_of_options;
+        ret.splice(at..at, profile_args.iter().cloned());
+    };
+    for arg in args {
+        if is_url_field_code(arg) {
+            insert_profile_args(&mut ret);
+            ret.extend(urls.iter().map(|url| match arg.as_str() {
+                "%f" | "%F" => local_path(url),
+                _ => url.clone(),
+            }));
+            inserted = true;
+            continue;
+        }
+        let expand = |url: &str| {
+            replace_field_codes(arg, |code, out| match code {
+                '%' => out.push('%'),
+                'u' | 'U' => out.push_str(url),
+                'f' | 'F' => out.push_str(&local_path(url)),
+                _ => (),
+            })
+        };
+        if url_field_code(arg).is_none() {
+            ret.push(expand(""));
+            continue;
+        }
+        // an argument like --url=%U is repeated for every URL
+        insert_profile_args(&mut ret);
+        ret.extend(urls.iter().map(|url| expand(url)));
+        inserted = true;
+    }
+    if !inserted {
+        insert_profile_args(&mut ret);
+        ret.extend_from_slice(urls);
+    }
+    ret
+}
@@ -0,0 +1,294 @@
+//! Browsers that have no desktop entry advertising http and https, found in
+//! search directories: AppImages, and extracted tarballs of Tor Browser,
+//! Mullvad Browser and Mozilla based browsers
+use crate::browser::*;
+use crate::desktop::*;
+use crate::ini::*;
+use crate::platform::*;
+use crate::types::*;
+use crate::*;
+use log::debug;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+use std::process::Stdio;
+
+/// Browsers started by a script in their bundle, which uses the profile in
+/// the bundle, so they have no profiles to choose from
+const BUNDLE_SCRIPTS: &[(&str, &str)] = &[
+    ("start-tor-browser", "Tor Browser"),
+    ("start-mullvad-browser", "Mullvad Browser"),
+];
+
+/// A discovered browser, and the desktop file name it has when it is
+/// installed from a package, which picks the hint to detect its profiles
+type Discovered = (Browser, Option<String>);
+
+/// Lists browsers in the search directories and in their subdirectories
+pub fn discover_browsers(search_dirs: &[String], hints: &[ProfileHint]) -> DetectedBrowsers {
+    let mut discovered: Vec<Discovered> = Vec::new();
+    let mut warnings: Vec<DetectionWarning> = Vec::new();
+    for search_dir in search_dirs {
+        if let Err(e) = discover_in(search_dir, hints, &mut discovered) {
+            warnings.push(DetectionWarning {
+                source: search_dir.clone(),
+                message: format!("cannot search browsers: {:#}", e),
+            });
+        }
+    }
+    // AppImages of two versions of a browser have the same id
+    let mut found: Vec<Discovered> = Vec::new();
+    for (browser, desktop_id) in discovered {
+        match found.iter().find(|(b, _)| b.id == browser.id) {
+            Some((first, _)) => warnings.push(DetectionWarning {
+                source: browser.command.clone(),
+                message: format!("skipped, {} is {} already", first.command, browser.id),
+            }),
+            None => found.push((browser, desktop_id)),
+        }
+    }
+    // commands are paths in the search directories, so they tell browsers apart
+    let desktop_ids: HashMap<String, String> = found
+        .iter()
+        .filter_map(|(browser, desktop_id)| Some((browser.command.clone(), desktop_id.clone()?)))
+        .collect();
+    let mut browsers: Vec<Browser> = found.into_iter().map(|(browser, _)| browser).collect();
+    warnings.extend(detect_profiles_parallel(&mut browsers, |browser| {
+        match desktop_ids.get(&browser.command) {
+            Some(desktop_id) => detect_hint_profiles(browser, desktop_id, hints),
+            None => Ok(()),
+        }
+    }));
+    DetectedBrowsers { browsers, warnings }
+}
+
+fn discover_in(search_dir: &str, hints: &[ProfileHint], found: &mut Vec<Discovered>) -> Result<()> {
+    let dir = PathBuf::from(expand_path(search_dir)?.remove(0));
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    // the search directory may be an extracted tarball itself
+    found.extend(discover_install(&dir));
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            found.extend(discover_install(&path));
+        } else if is_appimage(&path) {
+            found.extend(discover_appimage(&path, hints));
+        }
+    }
+    Ok(())
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+fn is_appimage(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("appimage"))
+}
+
+/// Detects profiles with the hint of the desktop file name that the browser
+/// has when it is installed from a package
+fn detect_hint_profiles(browser: &mut Browser, desktop_id: &str, hints: &[ProfileHint]) -> Result<()> {
+    match hints
+        .iter()
+        .find(|hint| WildMatch::new(&hint.exe_path).matches(desktop_id))
+    {
+        Some(hint) => get_hint_profiles(browser, hint),
+        None => Ok(()),
+    }
+}
+
+/// Recognizes Tor Browser and Mullvad Browser by their start scripts, and
+/// other Mozilla based browsers by application.ini next to their executable
+/// and the browser directory, which Thunderbird and other Mozilla based
+/// applications do not have
+fn discover_install(dir: &Path) -> Option<Discovered> {
+    let id = dir.file_name()?.to_string_lossy().to_string();
+    for (script, name) in BUNDLE_SCRIPTS {
+        for script in [dir.join(script), dir.join("Browser").join(script)] {
+            if is_executable(&script) {
+                let browser = Browser {
+                    id,
+                    name: name.to_string(),
+                    command: script.to_string_lossy().to_string(),
+                    args: vec![],
+                    profiles: vec![],
+                };
+                return Some((browser, None));
+            }
+        }
+    }
+
+    if !dir.join("browser").is_dir() {
+        return None;
+    }
+    let sections = read_ini(&dir.join("application.ini")).ok()?;
+    let app = sections.iter().find(|section| section.name == "App")?;
+    let app_name = app.get("Name")?;
+    // the executable of Firefox Nightly and Developer Edition is firefox too
+    let command = [app_name.to_lowercase(), String::from("firefox")]
+        .into_iter()
+        .map(|program| dir.join(program))
+        .find(|program| is_executable(program))?;
+    let browser = Browser {
+        id,
+        name: format!("{} {}", app_name, app.get("Version").unwrap_or_default())
+            .trim_end()
+            .to_string(),
+        command: command.to_string_lossy().to_string(),
+        args: vec![],
+        profiles: vec![],
+    };
+    Some((browser, Some(format!("{}.desktop", app_name.to_lowercase()))))
+}
+
+/// Offset of the squashfs image in an AppImage of type 2, which follows the
+/// ELF runtime that ends with its section header table
+fn appimage_offset(path: &Path) -> Result<u64> {
+    let mut header = [0u8; 64];
+    fs::File::open(path)?.read_exact(&mut header)?;
+    if &header[..4] != b"\x7fELF" || &header[8..11] != b"AI\x02" {
+        return Err(Error::msg("not an AppImage of type 2"));
+    }
+    let little_endian = header[5] == 1;
+    let read = |start: usize, len: usize| {
+        let bytes = &header[start..start + len];
+        let mut value = 0u64;
+        for (i, byte) in bytes.iter().enumerate() {
+            let shift = if little_endian { i } else { len - 1 - i } * 8;
+            value |= (*byte as u64) << shift;
+        }
+        value
+    };
+    // e_shoff, e_shentsize and e_shnum of ELF32 and ELF64
+    let (shoff, shentsize, shnum) = match header[4] {
+        1 => (read(0x20, 4), read(0x2e, 2), read(0x30, 2)),
+        2 => (read(0x28, 8), read(0x3a, 2), read(0x3c, 2)),
+        _ => return Err(Error::msg("invalid ELF class")),
+    };
+    Ok(shoff + shentsize * shnum)
+}
+
+/// Creates a new directory in the temporary directory that only the user can
+/// access, with a random name, so that no other user can swap what is
+/// extracted into it
+fn create_private_temp_dir(prefix: &str) -> Result<PathBuf> {
+    let mut last_error = None;
+    for _ in 0..8 {
+        let suffix = RandomState::new().build_hasher().finish();
+        let dir = env::temp_dir().join(format!("{}-{:016x}", prefix, suffix));
+        match fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Err(e) => last_error = Some(e),
+            _ => return Ok(dir),
+        }
+    }
+    Err(Error::from(last_error.unwrap()).context("cannot create a temporary directory"))
+}
+
+/// Desktop file name and [Desktop Entry] of the desktop file at the root of
+/// an AppImage, extracted with unsquashfs without running the AppImage
+fn read_appimage_desktop_entry(path: &Path) -> Result<(String, IniSection)> {
+    let offset = appimage_offset(path)?;
+    let temp_dir = create_private_temp_dir("bro-appimage")?;
+    // unsquashfs creates the destination itself and fails if it exists
+    let dest = temp_dir.join("root");
+    let status = Command::new("unsquashfs")
+        .args([
+            "-no-progress",
+            "-offset",
+            &offset.to_string(),
+            "-dest",
+        ])
+        .arg(&dest)
+        .arg(path)
+        .arg("*.desktop")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let entry = match status {
+        Err(e) => Err(Error::msg(format!("cannot run unsquashfs: {}", e))),
+        status if status.as_ref().is_ok_and(|status| status.success()) => {
+            read_extracted_desktop_entry(&dest)
+        }
+        _ => Err(Error::msg("unsquashfs failed")),
+    };
+    let _ = fs::remove_dir_all(&temp_dir);
+    entry
+}
+
+fn read_extracted_desktop_entry(dir: &Path) -> Result<(String, IniSection)> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "desktop")
+        {
+            let sections = read_ini(&path)?;
+            if let Some(section) = sections.into_iter().find(|s| s.name == "Desktop Entry") {
+                let file_name = path.file_name().unwrap_or_default();
+                return Ok((file_name.to_string_lossy().to_string(), section));
+            }
+        }
+    }
+    Err(Error::msg("no desktop file"))
+}
+
+/// Reads the embedded desktop entry of an AppImage, which has to advertise
+/// http or https. Without unsquashfs, the file name of AppImages of known
+/// browsers like LibreWolf-124.0-1.x86_64.AppImage is used instead. The id
+/// comes from the desktop file name, like librewolf.AppImage, so that it
+/// stays the same when the AppImage is updated
+fn discover_appimage(path: &Path, hints: &[ProfileHint]) -> Option<Discovered> {
+    let file_name = path.file_name()?.to_string_lossy().to_string();
+    let (desktop_id, name, args) = match read_appimage_desktop_entry(path) {
+        Err(e) => {
+            debug!("Cannot read the desktop entry of {}: {:#}", file_name, e);
+            let name = file_name.split(['-', '_', '.']).next()?.to_string();
+            let desktop_id = format!("{}.desktop", name.to_lowercase());
+            hints
+                .iter()
+                .find(|hint| WildMatch::new(&hint.exe_path).matches(&desktop_id))?;
+            (desktop_id, name, vec![])
+        }
+        entry => {
+            let (desktop_id, entry) = entry.ok()?;
+            let mimes = entry.get("MimeType").unwrap_or_default();
+            if !mimes
+                .split(';')
+                .any(|mime| mime == "x-scheme-handler/http" || mime == "x-scheme-handler/https")
+            {
+                return None;
+            }
+            let name = entry.get("Name").unwrap_or(&file_name).to_string();
+            // the program of Exec is AppRun inside the AppImage, only its arguments are kept
+            let keys = DesktopEntryKeys {
+                name: &name,
+                ..DesktopEntryKeys::default()
+            };
+            let args = entry
+                .get("Exec")
+                .and_then(|exec| parse_exec(exec, &keys).ok())
+                .map(|args| args[1..].to_vec())
+                .unwrap_or_default();
+            (desktop_id, name, args)
+        }
+    };
+    let browser = Browser {
+        id: format!("{}.AppImage", desktop_id.trim_end_matches(".desktop")),
+        name,
+        command: path.to_string_lossy().to_string(),
+        args,
+        profiles: vec![],
+    };
+    Some((browser, Some(desktop_id)))
+}
@@ -0,0 +1,339 @@
+use crate::browser::*;
+use crate::platform::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::*;
+use std::fmt;
+use std::path::Path;
+use std::time::Instant;
+
+/// Handler id reported by current_default_browser when bro is the default
+#[cfg(windows)]
+const BRO_HANDLER: &str = "BroHTTP";
+#[cfg(all(unix, not(target_os = "macos")))]
+const BRO_HANDLER: &str = "bro.desktop";
+#[cfg(target_os = "macos")]
+const BRO_HANDLER: &str = "bro.app";
+
+#[derive(Serialize, Debug)]
+pub struct ConfigCheck {
+    pub path: String,
+    pub exists: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DefaultBrowserCheck {
+    pub handler: Option<String>,
+    pub is_bro: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DesktopEntryCheck {
+    pub path: String,
+    pub exists: bool,
+    pub exec: Option<String>,
+    pub points_to_current_exe: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProfileCheck {
+    pub id: String,
+    pub path: String,
+    /// None when the profile directory is not recorded in the config
+    pub exists: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BrowserCheck {
+    pub id: String,
+    pub command: String,
+    pub command_exists: bool,
+    pub profiles: Vec<ProfileCheck>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DetectionCheck {
+    pub browsers: usize,
+    pub duration_ms: u128,
+    pub error: Option<String>,
+    /// Browsers and search directories that detection had to skip partly
+    pub warnings: Vec<DetectionWarning>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Diagnosis {
+    pub config: ConfigCheck,
+    pub default_browser: DefaultBrowserCheck,
+    pub desktop_entry: Option<DesktopEntryCheck>,
+    pub browsers: Vec<BrowserCheck>,
+    pub detection: DetectionCheck,
+}
+
+fn check_desktop_entry() -> Result<Option<DesktopEntryCheck>> {
+    if !cfg!(all(unix, not(target_os = "macos"))) {
+        return Ok(None);
+    }
+    let path = expand_path("~/.local/share/applications/bro.desktop")?.remove(0);
+    let exec = fs::read_to_string(&path).ok().and_then(|content| {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix("Exec="))
+            .map(String::from)
+    });
+    let exe = env::current_exe()?;
+    let points_to_current_exe = exec
+        .as_ref()
+        .is_some_and(|exec| exec.starts_with(&format!("{} ", exe.display())));
+    Ok(Some(DesktopEntryCheck {
+        exists: Path::new(&path).exists(),
+        path,
+        exec,
+        points_to_current_exe,
+    }))
+}
+
+fn check_browser(browser: &Browser) -> BrowserCheck {
+    BrowserCheck {
+        id: browser.id.clone(),
+        command: browser.command.clone(),
+        command_exists: command_exists(&browser.command),
+        profiles: browser
+            .profiles
+            .iter()
+            .filter(|profile| profile.id != "__PRIVATE__")
+            .map(|profile| ProfileCheck {
+                id: profile.id.clone(),
+                path: profile.path.clone(),
+                exists: if profile.path.is_empty() {
+                    None
+                } else {
+                    Some(Path::new(&profile.path).is_dir())
+                },
+            })
+            .collect(),
+    }
+}
+
+/// Checks the config, the registration and all detected browsers
+pub fn diagnose() -> Result<Diagnosis> {
+    let config_path = get_config_path()?;
+    let config = Config::load(&config_path);
+    let config_check = ConfigCheck {
+        path: config_path.display().to_string(),
+        exists: config_path.exists(),
+        error: config.as_ref().err().map(|e| format!("{:#}", e)),
+    };
+
+    let default_browser = match current_default_browser() {
+        Err(e) => DefaultBrowserCheck {
+            handler: None,
+            is_bro: false,
+            error: Some(format!("{:#}", e)),
+        },
+        handler => {
+            let handler = handler?;
+            DefaultBrowserCheck {
+                is_bro: handler.ends_with(BRO_HANDLER),
+                handler: Some(handler),
+                error: None,
+            }
+        }
+    };
+
+    let browsers = config
+        .as_ref()
+        .map(|config| config.detected_browsers.iter().map(check_browser).collect())
+        .unwrap_or_default();
+
+    let start = Instant::now();
+    let detection = config
+        .as_ref()
+        .map_or_else(|_| Detection::default(), Config::detection);
+    let detected = available_browsers(&detection);
+    let detection = match detected {
+        Err(e) => DetectionCheck {
+            duration_ms: start.elapsed().as_millis(),
+            browsers: 0,
+            error: Some(format!("{:#}", e)),
+            warnings: vec![],
+        },
+        detected => {
+            let detected = detected?;
+            DetectionCheck {
+                duration_ms: start.elapsed().as_millis(),
+                browsers: detected.browsers.len(),
+                error: None,
+                warnings: detected.warnings,
+            }
+        }
+    };
+
+    Ok(Diagnosis {
+        config: config_check,
+        default_browser,
+        desktop_entry: check_desktop_entry()?,
+        browsers,
+        detection,
+    })
+}
+
+impl Diagnosis {
+    pub fn problems(&self) -> usize {
+        let mut problems = 0;
+        problems += self.config.error.is_some() as usize;
+        // a default browser that cannot be read is one problem, not also a wrong one
+        let default_browser = &self.default_browser;
+        problems += (default_browser.error.is_some()
+            || (default_browser.handler.is_some() && !default_browser.is_bro))
+            as usize;
+        if let Some(entry) = &self.desktop_entry {
+            problems += !entry.points_to_current_exe as usize;
+        }
+        for browser in &self.browsers {
+            problems += !browser.command_exists as usize;
+            problems += browser
+                .profiles
+                .iter()
+                .filter(|p| p.exists == Some(false))
+                .count();
+        }
+        problems += self.detection.error.is_some() as usize;
+        problems += self.detection.warnings.len();
+        problems
+    }
+}
+
+fn status(ok: bool) -> &'static str {
+    if ok {
+        "[ok]"
+    } else {
+        "[!!]"
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = &self.config;
+        match &config.error {
+            None => writeln!(f, "{} config {} parses", status(true), config.path)?,
+            Some(error) => writeln!(f, "{} {}", status(false), error)?,
+        }
+
+        let default_browser = &self.default_browser;
+        match (&default_browser.handler, &default_browser.error) {
+            (Some(handler), _) if default_browser.is_bro => {
+                writeln!(f, "{} default browser is bro ({})", status(true), handler)?
+            }
+            (Some(handler), _) => writeln!(
+                f,
+                "{} default browser is {}, run `bro register`",
+                status(false),
+                handler
+            )?,
+            (None, error) => writeln!(
+                f,
+                "{} default browser is unknown: {}",
+                status(false),
+                error.as_deref().unwrap_or_default()
+            )?,
+        }
+
+        if let Some(entry) = &self.desktop_entry {
+            match &entry.exec {
+                _ if entry.points_to_current_exe => writeln!(
+                    f,
+                    "{} {} points to the current executable",
+                    status(true),
+                    entry.path
+                )?,
+                Some(exec) => writeln!(
+                    f,
+                    "{} {} runs `{}` instead of the current executable, run `bro register`",
+                    status(false),
+                    entry.path,
+                    exec
+                )?,
+                None if entry.exists => writeln!(
+                    f,
+                    "{} {} has no Exec line, run `bro register`",
+                    status(false),
+                    entry.path
+                )?,
+                None => writeln!(
+                    f,
+                    "{} {} does not exist, run `bro register`",
+                    status(false),
+                    entry.path
+                )?,
+            }
+        }
+
+        for browser in &self.browsers {
+            writeln!(
+                f,
+                "{} browser {}: command {}{}",
+                status(browser.command_exists),
+                browser.id,
+                browser.command,
+                if browser.command_exists {
+                    ""
+                } else {
+                    " does not exist, run `bro config --refresh`"
+                }
+            )?;
+            for profile in &browser.profiles {
+                match profile.exists {
+                    Some(true) => writeln!(
+                        f,
+                        "    {} profile {}: {}",
+                        status(true),
+                        profile.id,
+                        profile.path
+                    )?,
+                    Some(false) => writeln!(
+                        f,
+                        "    {} profile {}: {} does not exist",
+                        status(false),
+                        profile.id,
+                        profile.path
+                    )?,
+                    None => writeln!(
+                        f,
+                        "    [??] profile {}: directory is not recorded, run `bro config --refresh`",
+                        profile.id
+                    )?,
+                }
+            }
+        }
+
+        let detection = &self.detection;
+        match &detection.error {
+            None => writeln!(
+                f,
+                "{} detected {} browsers in {} ms",
+                status(true),
+                detection.browsers,
+                detection.duration_ms
+            )?,
+            Some(error) => writeln!(
+                f,
+                "{} detection failed after {} ms: {}",
+                status(false),
+                detection.duration_ms,
+                error
+            )?,
+        }
+        for warning in &detection.warnings {
+            writeln!(
+                f,
+                "    {} {}: {}",
+                status(false),
+                warning.source,
+                warning.message
+            )?;
+        }
+        fmt::Result::Ok(())
+    }
+}
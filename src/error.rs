@@ -0,0 +1,116 @@
+use crate::types::*;
+use crate::*;
+use std::fmt;
+
+/// Process exit codes of bro, documented in `bro --help`
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONFIG: i32 = 3;
+pub const EXIT_BROWSER: i32 = 4;
+pub const EXIT_LAUNCH: i32 = 5;
+pub const EXIT_PROBLEMS: i32 = 6;
+
+#[derive(Debug)]
+pub enum BroError {
+    ConfigNotFound(PathBuf),
+    InvalidConfig { path: PathBuf, reason: String },
+    InvalidRule { index: usize, rule: Rule, reason: String },
+    UnknownRule(usize),
+    UnknownMode(String),
+    NoMatchingRule(String),
+    UnknownBrowser(String),
+    UnknownProfile { browser: String, profile: String },
+    InvalidSpec(String),
+    InvalidUrl(String),
+    NoHistoryEntry(String),
+    Launch { command: String, reason: String },
+    /// Problems found by `bro doctor`, which are listed in its report
+    ProblemsFound(usize),
+}
+
+impl BroError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BroError::InvalidUrl(_)
+            | BroError::NoHistoryEntry(_)
+            | BroError::UnknownRule(_)
+            | BroError::UnknownMode(_) => EXIT_USAGE,
+            BroError::ConfigNotFound(_)
+            | BroError::InvalidConfig { .. }
+            | BroError::InvalidRule { .. }
+            | BroError::NoMatchingRule(_) => EXIT_CONFIG,
+            BroError::UnknownBrowser(_)
+            | BroError::UnknownProfile { .. }
+            | BroError::InvalidSpec(_) => EXIT_BROWSER,
+            BroError::Launch { .. } => EXIT_LAUNCH,
+            BroError::ProblemsFound(_) => EXIT_PROBLEMS,
+        }
+    }
+}
+
+impl fmt::Display for BroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroError::ConfigNotFound(path) => write!(
+                f,
+                "config {} not found, run `bro config --init` to create one",
+                path.display()
+            ),
+            BroError::InvalidConfig { path, reason } => {
+                write!(f, "invalid config {}: {}", path.display(), reason)
+            }
+            BroError::InvalidRule {
+                index,
+                rule,
+                reason,
+            } => write!(f, "rule #{} `{}` is invalid: {}", index, rule, reason),
+            BroError::UnknownRule(index) => {
+                write!(f, "rule #{} does not exist, run `bro rule list`", index)
+            }
+            BroError::UnknownMode(mode) => {
+                write!(f, "unknown mode {}, run `bro mode` to list modes", mode)
+            }
+            BroError::NoMatchingRule(uri) => {
+                write!(f, "no rule matches {}, add a FINAL rule", uri)
+            }
+            BroError::UnknownBrowser(browser) => write!(f, "unknown browser {}", browser),
+            BroError::UnknownProfile { browser, profile } => {
+                write!(f, "unknown profile {} for browser {}", profile, browser)
+            }
+            BroError::InvalidSpec(spec) => write!(
+                f,
+                "invalid browser specification {}, expected <browser.id>, <browser.id>:<profile.id>, <browser.id>:@<email> or <browser.id>:name=<profile.name>",
+                spec
+            ),
+            BroError::InvalidUrl(uri) => {
+                write!(f, "{} is not a URL, it must start with a scheme like https://", uri)
+            }
+            BroError::NoHistoryEntry(entry) => write!(
+                f,
+                "no history entry {}, run `bro history` to list entries",
+                entry
+            ),
+            BroError::Launch { command, reason } => {
+                write!(f, "cannot launch {}: {}", command, reason)
+            }
+            BroError::ProblemsFound(problems) => write!(f, "{} problem(s) found", problems),
+        }
+    }
+}
+
+impl std::error::Error for BroError {}
+
+/// Finds the exit code of the first bro error in the error chain
+pub fn exit_code(err: &Error) -> i32 {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<BroError>())
+        .map_or(EXIT_FAILURE, BroError::exit_code)
+}
+
+/// Whether the error comes from writing to a pipe that has been closed
+pub fn is_broken_pipe(err: &Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<std::io::Error>())
+        .any(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+}
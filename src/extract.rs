@@ -0,0 +1,65 @@
+use crate::*;
+
+const URL_PATTERN: &str = concat!(
+    // <https://example.com/a b> or <URL:...>, which may be wrapped across lines
+    r"<(?:URL:)?\s*(?P<angle>(?:[a-zA-Z][a-zA-Z0-9+.-]*://|www\.)[^<>]*)>",
+    r"|(?P<scheme>\b[a-zA-Z][a-zA-Z0-9+.-]*://[^\s<>]+)", // https://example.com/
+    r"|(?P<www>\bwww\.[a-zA-Z0-9-]+\.[^\s<>]+)",          // www.example.com
+);
+
+/// Removes punctuation that ends a sentence rather than the URL, closing
+/// brackets are kept only when they are balanced inside the URL
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '"', '`']);
+        let trimmed = match trimmed.chars().last() {
+            Some(close @ (')' | ']' | '}')) => {
+                let open = match close {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if trimmed.matches(open).count() < trimmed.matches(close).count() {
+                    &trimmed[..trimmed.len() - 1]
+                } else {
+                    trimmed
+                }
+            }
+            _ => trimmed,
+        };
+        if trimmed == url {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+/// Finds URLs in free text: URLs with a scheme, bare www. hosts and
+/// angle-bracketed links, duplicates are removed and order is kept
+pub fn extract_uris(text: &str) -> Vec<String> {
+    let re = Regex::new(URL_PATTERN).unwrap();
+    let mut ret: Vec<String> = Vec::new();
+    for caps in re.captures_iter(text) {
+        let uri = if let Some(m) = caps.name("angle") {
+            // whitespace inside angle brackets is not part of the URL, as in
+            // RFC 3986 Appendix C
+            let inner: String = m.as_str().split_whitespace().collect();
+            if inner.starts_with("www.") {
+                format!("https://{}", inner)
+            } else {
+                inner
+            }
+        } else if let Some(m) = caps.name("scheme") {
+            trim_url(m.as_str()).to_string()
+        } else if let Some(m) = caps.name("www") {
+            format!("https://{}", trim_url(m.as_str()))
+        } else {
+            continue;
+        };
+        if !ret.contains(&uri) {
+            ret.push(uri);
+        }
+    }
+    ret
+}
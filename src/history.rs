@@ -0,0 +1,125 @@
+use crate::error::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::{fs, Deserialize, Error, PathBuf, Result, Serialize};
+use std::time::{Duration, SystemTime};
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            enabled: true,
+            max_entries: 1000,
+            max_age: String::from("90d"),
+        }
+    }
+}
+
+impl HistoryConfig {
+    fn max_age(&self) -> Result<Option<Duration>> {
+        if self.max_age.is_empty() {
+            return Ok(None);
+        }
+        let max_age = humantime::parse_duration(&self.max_age)
+            .map_err(|e| Error::msg(format!("invalid history max_age {}: {}", self.max_age, e)))?;
+        Ok(Some(max_age))
+    }
+}
+
+/// One URL that has been opened, one JSON line in history.jsonl
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    pub ts: String,
+    pub url: String,
+    pub spec: String,
+    /// None when the browser specification has been given on the command line
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rule: Option<usize>,
+}
+
+impl HistoryEntry {
+    pub fn new(url: &str, spec: &str, rule: Option<usize>) -> HistoryEntry {
+        HistoryEntry {
+            ts: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            url: url.to_string(),
+            spec: spec.to_string(),
+            rule,
+        }
+    }
+
+    pub fn time(&self) -> Option<SystemTime> {
+        humantime::parse_rfc3339_weak(&self.ts).ok()
+    }
+}
+
+fn history_path() -> Result<PathBuf> {
+    let mut path = get_state_dir()?;
+    path.push("history.jsonl");
+    Ok(path)
+}
+
+/// Reads the history from oldest to newest, lines that cannot be parsed are skipped
+pub fn load_history() -> Result<Vec<HistoryEntry>> {
+    let contents = match fs::read_to_string(history_path()?) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        other => other?,
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Appends entries and drops the ones beyond max_entries and max_age
+pub fn record_history(config: &HistoryConfig, entries: Vec<HistoryEntry>) -> Result<()> {
+    if !config.enabled || entries.is_empty() {
+        return Ok(());
+    }
+    let mut history = load_history()?;
+    history.extend(entries);
+    if let Some(max_age) = config.max_age()? {
+        let oldest = SystemTime::now() - max_age;
+        history.retain(|entry| entry.time().is_some_and(|time| time >= oldest));
+    }
+    if history.len() > config.max_entries {
+        history.drain(..history.len() - config.max_entries);
+    }
+
+    let mut contents = String::new();
+    for entry in &history {
+        contents.push_str(&serde_json::to_string(entry)?);
+        contents.push('\n');
+    }
+    write_atomically(&history_path()?, contents.as_bytes())
+}
+
+pub fn clear_history() -> Result<()> {
+    match fs::remove_file(history_path()?) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        other => Ok(other?),
+    }
+}
+
+/// Parses a duration back from now like 2h or 3d, or a date like 2024-01-31
+/// or 2024-01-31T08:00:00Z
+pub fn parse_since(since: &str) -> Result<SystemTime> {
+    if let Ok(duration) = humantime::parse_duration(since) {
+        return Ok(SystemTime::now() - duration);
+    }
+    parse_time(since).map_err(|_| {
+        Error::msg(format!(
+            "invalid time {}, expected a duration like 2h or a date like 2024-01-31",
+            since
+        ))
+    })
+}
+
+/// Finds an entry by its number in `bro history`, 1 being the newest, or by its URL
+pub fn find_history_entry(history: &[HistoryEntry], entry: &str) -> Result<HistoryEntry> {
+    let found = match entry.parse::<usize>() {
+        Ok(n) if n > 0 => history.iter().rev().nth(n - 1),
+        _ => history.iter().rev().find(|e| e.url == entry),
+    };
+    found
+        .cloned()
+        .ok_or_else(|| BroError::NoHistoryEntry(entry.to_string()).into())
+}
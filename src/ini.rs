@@ -0,0 +1,66 @@
+//! Minimal INI parser, enough for Firefox profiles.ini and installs.ini
+use crate::{fs, Result};
+use anyhow::Context;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct IniSection {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+impl IniSection {
+    /// Value of the last entry of the key, keys are case-insensitive
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Parses sections in order, entries before the first section header are put
+/// in a section with an empty name. Comments start with `;` or `#`, lines
+/// that are neither headers nor `key=value` are ignored
+pub fn parse_ini(text: &str) -> Vec<IniSection> {
+    let mut sections: Vec<IniSection> = Vec::new();
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push(IniSection {
+                name: name.trim().to_string(),
+                entries: vec![],
+            });
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if sections.is_empty() {
+            sections.push(IniSection {
+                name: String::new(),
+                entries: vec![],
+            });
+        }
+        let section = sections.last_mut().unwrap();
+        section
+            .entries
+            .push((key.trim().to_string(), value.trim().to_string()));
+    }
+    sections
+}
+
+/// Parses an INI file, a missing file has no sections
+pub fn read_ini(path: &Path) -> Result<Vec<IniSection>> {
+    match fs::read_to_string(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        contents => {
+            let contents = contents.with_context(|| format!("cannot read {}", path.display()))?;
+            Ok(parse_ini(&contents))
+        }
+    }
+}
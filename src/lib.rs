@@ -1,6 +1,17 @@
 pub mod browser;
+pub mod config;
+pub mod desktop;
+#[cfg(all(unix, not(target_os = "macos")))]
+pub mod discovery;
+pub mod doctor;
+pub mod error;
+pub mod extract;
+pub mod history;
+pub mod ini;
+pub mod logger;
 pub mod platform;
 pub mod rule;
+pub mod state;
 pub mod types;
 #[cfg(target_os = "macos")]
 pub mod macos;
@@ -0,0 +1,194 @@
+use crate::types::*;
+use crate::utils::*;
+use crate::{fs, Deserialize, PathBuf, Result, Serialize};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            enabled: false,
+            level: String::from("info"),
+            path: String::new(),
+            max_size: 1024 * 1024,
+            max_files: 3,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Reads only the log section of the config, so that logging works even
+    /// when the rest of the config is invalid
+    pub fn load(config_path: &Path) -> LogConfig {
+        #[derive(Deserialize)]
+        struct LogSection {
+            #[serde(default)]
+            log: LogConfig,
+        }
+        fs::read_to_string(config_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<LogSection>(&contents).ok())
+            .map(|section| section.log)
+            .unwrap_or_default()
+    }
+
+    pub fn log_path(&self) -> Result<PathBuf> {
+        if !self.path.is_empty() {
+            return Ok(PathBuf::from(&self.path));
+        }
+        let mut path = get_state_dir()?;
+        path.push("bro.log");
+        Ok(path)
+    }
+}
+
+/// Log file that is rotated to bro.log.1, bro.log.2, ... when it grows over
+/// max_size, keeping at most max_files rotated files
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> Result<()> {
+        let _ = fs::remove_file(self.rotated_path(self.max_files));
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&self, line: &str) -> Result<()> {
+        let size = fs::metadata(&self.path).map_or(0, |m| m.len());
+        if size > 0 && size + line.len() as u64 + 1 > self.max_size {
+            self.rotate()?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+}
+
+static LOG_FILE: OnceLock<Mutex<RotatingFile>> = OnceLock::new();
+
+fn timestamp() -> String {
+    humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+fn write_log_line(value: serde_json::Value) {
+    if let Some(file) = LOG_FILE.get() {
+        if let Ok(file) = file.lock() {
+            // logging must never break opening URLs
+            let _ = file.write_line(&value.to_string());
+        }
+    }
+}
+
+/// Forwards records to env_logger on stderr and, at its own level, to the log file
+struct BroLogger {
+    stderr: env_logger::Logger,
+    file_level: LevelFilter,
+}
+
+impl Log for BroLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) || metadata.level() <= self.file_level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.stderr.matches(record) {
+            self.stderr.log(record);
+        }
+        if record.level() <= self.file_level {
+            write_log_line(serde_json::json!({
+                "ts": timestamp(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            }));
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+    }
+}
+
+/// Installs the logger, stderr_level overrides RUST_LOG when it is set
+pub fn init_logger(stderr_level: Option<LevelFilter>, log_config: &LogConfig) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    if let Some(level) = stderr_level {
+        builder.filter_level(level);
+    }
+    let stderr = builder.build();
+
+    let mut file_level = LevelFilter::Off;
+    if log_config.enabled {
+        if let Ok(path) = log_config.log_path() {
+            let _ = LOG_FILE.set(Mutex::new(RotatingFile {
+                path,
+                max_size: log_config.max_size,
+                max_files: log_config.max_files,
+            }));
+            file_level = LevelFilter::from_str(&log_config.level).unwrap_or(LevelFilter::Info);
+        }
+    }
+
+    log::set_max_level(stderr.filter().max(file_level));
+    let _ = log::set_boxed_logger(Box::new(BroLogger { stderr, file_level }));
+}
+
+/// How one URL has been routed and opened
+#[derive(Serialize, Debug)]
+pub struct Decision<'a> {
+    /// URL as it has been passed to bro
+    pub input: &'a str,
+    /// URL as it has been passed to the browser
+    pub url: &'a str,
+    /// Mode of the rule, None for base rules
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<&'a str>,
+    pub rule: Option<usize>,
+    pub spec: &'a str,
+    /// Whether the spec comes from `bro pin` rather than the rules
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// `ok` or the error message
+    pub result: String,
+}
+
+/// Writes one JSON line of an open decision into the log file when it is enabled
+pub fn log_decision(decision: &Decision) {
+    let mut value = serde_json::json!({
+        "ts": timestamp(),
+        "level": "INFO",
+        "target": "bro::decision",
+    });
+    if let serde_json::Value::Object(fields) = serde_json::json!(decision) {
+        value.as_object_mut().unwrap().extend(fields);
+    }
+    write_log_line(value);
+}
@@ -131,7 +131,7 @@ pub fn unregister() -> Result<()> {
     Ok(())
 }
 
-pub fn available_browsers() -> Result<Vec<Browser>> {
+pub fn available_browsers(detection: &Detection) -> Result<DetectedBrowsers> {
     // get from Info.plist in every app in /Applications that can handle http and https
     let mut app_path = vec![PathBuf::from("/Applications")];
     let mut user_apps = PathBuf::from(env::var("HOME")?);
@@ -139,6 +139,7 @@ pub fn available_browsers() -> Result<Vec<Browser>> {
     app_path.push(user_apps);
 
     let mut browsers = Vec::<Browser>::new();
+    let mut warnings = Vec::<DetectionWarning>::new();
     for path in app_path.iter() {
         let dir = std::fs::read_dir(path);
         if dir.is_err() {
@@ -151,13 +152,21 @@ pub fn available_browsers() -> Result<Vec<Browser>> {
                 println!("Error getting item {:?}: {:?}", app_path, maybe_app.err());
                 continue;
             }
-            let app = maybe_app.unwrap();
+            let app = maybe_app?;
             // App must be a directory and end with .app
-            if !app.file_type().unwrap().is_dir()
-                || !app.file_name().to_str().unwrap().ends_with(".app")
+            if !app.file_type().is_ok_and(|t| t.is_dir())
+                || !app.file_name().to_string_lossy().ends_with(".app")
             {
                 continue;
             }
+            let (file_name, app_dir) = (app.file_name(), app.path());
+            let (Some(file_name), Some(command)) = (file_name.to_str(), app_dir.to_str()) else {
+                warnings.push(DetectionWarning {
+                    source: app_dir.to_string_lossy().to_string(),
+                    message: String::from("path is not UTF-8"),
+                });
+                continue;
+            };
             let mut plist_path = app.path();
             plist_path.push("Contents");
             plist_path.push("Info.plist");
@@ -171,17 +180,41 @@ pub fn available_browsers() -> Result<Vec<Browser>> {
             }
 
             browsers.push(Browser {
-                id: app.file_name().into_string().unwrap(),
-                name: app.file_name().into_string().unwrap(),
-                command: app.path().into_os_string().into_string().unwrap(),
+                id: file_name.to_string(),
+                name: file_name.to_string(),
+                command: command.to_string(),
+                args: vec![],
                 profiles: vec![],
             });
         }
     }
-    browsers.iter_mut().for_each(|b| {
-        get_profiles(b).unwrap();
-    });
-    Ok(browsers)
+    warnings.extend(detect_profiles_parallel(&mut browsers, |b| {
+        get_profiles(b, &detection.hints)
+    }));
+    Ok(DetectedBrowsers { browsers, warnings })
+}
+
+/// Detects one browser again by its application name, None when it is gone
+pub fn detect_browser(id: &str, detection: &Detection) -> Result<Option<Browser>> {
+    let mut user_apps = PathBuf::from(env::var("HOME")?);
+    user_apps.push("Applications");
+    for dir in [PathBuf::from("/Applications"), user_apps] {
+        let app_dir = dir.join(id);
+        let plist_path = app_dir.join("Contents").join("Info.plist");
+        if !plist_path.exists() || unsafe { !app_can_handle_http_or_https(&plist_path) } {
+            continue;
+        }
+        let mut browser = Browser {
+            id: id.to_string(),
+            name: id.to_string(),
+            command: app_dir.to_string_lossy().to_string(),
+            args: vec![],
+            profiles: vec![],
+        };
+        get_profiles(&mut browser, &detection.hints)?;
+        return Ok(Some(browser));
+    }
+    Ok(None)
 }
 
 unsafe fn app_can_handle_http_or_https(path: &PathBuf) -> bool {
@@ -230,7 +263,7 @@ pub fn expand_path(path: &str) -> Result<Vec<String>> {
 }
 
 pub fn detect_path(browser: &Browser, hint: &ProfileHint) -> Result<String> {
-    if browser.id.as_str() == hint.exe_path {
+    if WildMatch::new(&hint.exe_path).matches(&browser.id) {
         return Ok(browser.command.clone());
     }
     Ok(String::new())
@@ -1,67 +1,1088 @@
 use bro::browser::*;
+use bro::doctor::*;
+use bro::error::*;
+use bro::extract::*;
+use bro::history::*;
+use bro::logger::*;
 use bro::platform::*;
 use bro::rule::*;
 use bro::types::*;
 use bro::utils::*;
 use bro::*;
+use anyhow::Context;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::Shell;
+use log::{info, warn, LevelFilter};
+use serde_json::Value;
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::SystemTime;
 
-#[derive(Serialize, Deserialize)]
-struct Config {
-    detected_browsers: Vec<Browser>,
-    custom_browsers: Vec<Browser>,
-    rules: Vec<Rule>,
+const EXIT_CODES: &str = "Exit codes:
+  0  Success
+  1  Unexpected error
+  2  Invalid command line or URL
+  3  Config file is missing or invalid, or a rule is invalid
+  4  Browser or profile of a browser specification is unknown
+  5  Browser cannot be launched
+  6  bro doctor found problems";
+
+#[derive(Parser)]
+#[command(version, about, after_help = EXIT_CODES)]
+struct Cli {
+    /// Print more diagnostics (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Open URLs in the browsers chosen by the rules
+    Open {
+        /// URLs to open, URLs for the same browser and profile open in one window,
+        /// `-` reads one URL per line from standard input
+        #[arg(required = true)]
+        urls: Vec<String>,
+        /// Open the URLs with this browser specification instead of the rules
+        #[arg(long, value_name = "SPEC")]
+        with: Option<String>,
+        /// Open the URLs in the private browsing profile of the chosen browser
+        #[arg(long)]
+        private: bool,
+        /// Print the commands that would open the URLs instead of running them
+        #[arg(long, visible_alias = "dry-run")]
+        print: bool,
+    },
+    /// Open URLs found in free text, such as a chat log or an email body
+    Extract {
+        /// List the URLs with their routing decisions instead of opening them
+        #[arg(long)]
+        list: bool,
+        /// Files to scan, standard input is scanned when no file is given
+        #[arg(value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+    },
+    /// List opened URLs and the browser specifications they were opened with
+    History {
+        /// Only list entries whose URL or browser specification matches this regular expression
+        #[arg(long, value_name = "PATTERN")]
+        grep: Option<String>,
+        /// Only list entries since a duration ago like 2h or 3d, or since a date like 2024-01-31
+        #[arg(long, value_name = "WHEN")]
+        since: Option<String>,
+        /// Print entries as JSON
+        #[arg(long)]
+        json: bool,
+        /// Delete the whole history
+        #[arg(long, conflicts_with_all = ["grep", "since", "json"])]
+        clear: bool,
+    },
+    /// Open a URL from the history again, in another browser or profile
+    Reopen {
+        /// Entry number as listed by `bro history` (1 is the newest) or its URL
+        entry: String,
+        /// Browser specification to open the URL with, the recorded one by default
+        #[arg(long, value_name = "SPEC")]
+        with: Option<String>,
+    },
+    /// Register bro as the default browser
+    Register,
+    /// Unregister bro as the default browser
+    Unregister,
+    /// List detected and custom browsers
+    Browsers {
+        /// Print browsers and their profiles as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List profiles of a browser
+    Profiles {
+        /// Browser id, as listed by `bro browsers`
+        browser: String,
+        /// Print profiles as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List rules with their indices
+    Rules {
+        /// Print rules and their compiled matchers as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add, move, remove, enable or disable rules
+    #[command(subcommand)]
+    Rule(RuleCommands),
+    /// Show or switch the active mode, whose rules are matched before the base rules
+    Mode {
+        #[command(subcommand)]
+        command: Option<ModeCommands>,
+    },
+    /// Open all URLs with one browser specification instead of the rules, or show the active pin
+    Pin {
+        /// Browser specification to open all URLs with
+        #[arg(value_name = "SPEC")]
+        spec: Option<String>,
+        /// Unpin automatically after this duration, e.g. 30m or 1h
+        #[arg(long = "for", value_name = "DURATION", requires = "spec")]
+        duration: Option<humantime::Duration>,
+    },
+    /// Remove the pin, so that URLs are routed by the rules again
+    Unpin,
+    /// Validate the config file, its rules and their browser specifications
+    Check,
+    /// Diagnose the config, the registration and detected browsers
+    Doctor {
+        /// Print the diagnosis as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a completion script that completes browser ids and specifications
+    Completions { shell: Shell },
+    /// Print values for completion scripts
+    #[command(name = "__complete", hide = true)]
+    Complete { kind: ValueKind },
+    /// Show the config file path and format, or create or refresh it
+    Config {
+        /// Create an example config with detected browsers if there is none
+        #[arg(long)]
+        init: bool,
+        /// Detect browsers again and save them into detected_browsers
+        #[arg(long, conflicts_with = "init")]
+        refresh: bool,
+    },
+}
+
+/// Kinds of live values that `bro __complete` prints
+#[derive(ValueEnum, Clone, Copy)]
+enum ValueKind {
+    /// Browser ids
+    Browsers,
+    /// Browser specifications, browser ids and <browser.id>:<profile.id>
+    Specs,
+    /// Rule matcher names
+    Matchers,
+}
+
+#[derive(Subcommand)]
+enum RuleCommands {
+    /// List rules with their indices
+    List {
+        /// Print rules and their compiled matchers as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add a rule, before the FINAL rule unless --at is given
+    Add {
+        #[arg(value_name = "MATCHER")]
+        matcher: String,
+        /// Pattern of the matcher, omitted for FINAL rules
+        #[arg(value_name = "PATTERN")]
+        pattern: String,
+        /// Browser specification of the rule
+        #[arg(value_name = "SPEC")]
+        spec: Option<String>,
+        /// Index to insert the rule at
+        #[arg(long, value_name = "INDEX")]
+        at: Option<usize>,
+        /// Do not match the rule before this date or time, e.g. 2024-01-31
+        #[arg(long, value_name = "WHEN")]
+        not_before: Option<String>,
+        /// Stop matching the rule from this date or time on, e.g. 2024-04-30
+        #[arg(long, value_name = "WHEN")]
+        expires_at: Option<String>,
+    },
+    /// Move a rule to another index
+    Move { from: usize, to: usize },
+    /// Remove a rule
+    Remove { index: usize },
+    /// Enable a disabled rule
+    Enable { index: usize },
+    /// Disable a rule without removing it
+    Disable { index: usize },
+    /// List rules whose expires_at has passed, in the base rules and all modes
+    Expired,
+    /// Remove expired rules from the base rules and all modes
+    Prune {
+        /// Remove them without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModeCommands {
+    /// Activate a mode defined in the modes section of the config
+    Set { name: String },
+    /// Deactivate the active mode, so that only the base rules are matched
+    Clear,
+}
+
+fn in_config(path: &Path) -> String {
+    format!("in config {}", path.display())
+}
+
+/// How `bro open` chooses and launches browsers instead of the rules
+#[derive(Default)]
+struct OpenOptions {
+    /// Browser specification that is used for all URLs
+    with: Option<String>,
+    /// Open URLs in the private browsing profile of the chosen browser
+    private: bool,
+    /// Print the commands instead of running them
+    print: bool,
+}
+
+/// Loads the state, an invalid state is ignored so that URLs still open
+fn load_state() -> State {
+    State::load().unwrap_or_else(|err| {
+        warn!("{:#}, the base rules are used", err);
+        State::default()
+    })
+}
+
+/// Compiles the rules of the active mode followed by the base rules
+fn compile_active_rules(
+    config: &Config,
+    config_path: &Path,
+    state: &State,
+) -> Result<Vec<CompiledRule>> {
+    config
+        .compile_active_rules(state.mode.as_deref())
+        .with_context(|| in_config(config_path))
+}
+
+/// Spawns the commands that open the URLs, all of them even when one fails
+fn spawn_launches(uris: &[String], cmds: &[(String, Vec<String>)]) -> Result<()> {
+    let mut result = Ok(());
+    for cmd in cmds {
+        info!("{:?} uses {:?}", uris, cmd);
+        if let Err(err) = spawn_shell_command(&cmd.0, &cmd.1) {
+            result = result.and(Err(err));
+        }
+    }
+    result
+}
+
+/// Routes every URL independently, then opens URLs that resolve to the same
+/// browser specification in one browser process
+fn open_uris(uris: &[String], options: &OpenOptions) -> Result<()> {
+    let config_path = get_config_path()?;
+    let mut config = Config::load(&config_path)?;
+    let state = load_state();
+    let pin = match options.with {
+        Some(_) => None,
+        None => state.active_pin(),
+    };
+    if let Some(pin) = pin {
+        info!("All URLs are pinned to {}", pin);
+    }
+    let with = options.with.as_ref().or(pin.map(|pin| &pin.spec));
+    let compiled = match with {
+        Some(_) => vec![],
+        None => compile_active_rules(&config, &config_path, &state)?,
+    };
+
+    let mut failures: Vec<Error> = Vec::new();
+    let mut history: Vec<HistoryEntry> = Vec::new();
+    let mut batches: Vec<(String, Option<&CompiledRule>, Vec<String>)> = Vec::new();
+    for uri in uris {
+        let routed = if !is_uri(uri) {
+            Err(Error::new(BroError::InvalidUrl(uri.to_string())))
+        } else if let Some(spec) = with {
+            Ok((spec.clone(), None))
+        } else {
+            match_rule_by(&compiled, uri, config.match_strategy)
+                .map(|rule| (rule.browser.clone(), Some(rule)))
+                .ok_or_else(|| {
+                    Error::new(BroError::NoMatchingRule(uri.to_string()))
+                        .context(in_config(&config_path))
+                })
+        };
+        let (spec, rule) = match routed {
+            Err(err) => {
+                log_decision(&Decision {
+                    input: uri,
+                    url: uri,
+                    mode: None,
+                    rule: None,
+                    spec: "",
+                    pinned: false,
+                    result: format!("{:#}", err),
+                });
+                failures.push(err);
+                continue;
+            }
+            routed => routed?,
+        };
+        let spec = if options.private {
+            private_spec(&spec)
+        } else {
+            spec
+        };
+        match batches.iter_mut().find(|(s, _, _)| *s == spec) {
+            Some((_, _, batch)) => batch.push(uri.clone()),
+            None => batches.push((spec, rule, vec![uri.clone()])),
+        }
+    }
+
+    let detection = config.detection();
+    let mut redetected: Vec<&str> = Vec::new();
+    for (spec, rule, batch) in &batches {
+        // browsers may have been updated or moved since they were detected,
+        // every browser is re-detected at most once
+        let browser_id = spec.split(':').next().unwrap_or_default();
+        if !redetected.contains(&browser_id) {
+            redetected.push(browser_id);
+            if refresh_stale_browser(&mut config.detected_browsers, browser_id, &detection)
+                .is_some()
+            {
+                warn!(
+                    "Browser {} has been re-detected, run `bro config --refresh` to persist it in {}",
+                    browser_id,
+                    config_path.display()
+                );
+            }
+        }
+
+        let cmds = launch_browser_command(&config.browsers(), spec, batch).with_context(|| {
+            match rule {
+                Some(rule) => format!("{} {}", rule, in_config(&config_path)),
+                None => in_config(&config_path),
+            }
+        });
+        if options.print {
+            match cmds {
+                Err(err) => failures.push(err),
+                cmds => {
+                    for (exe, args) in cmds? {
+                        let mut line = shell_quote(&exe);
+                        for arg in &args {
+                            line.push(' ');
+                            line.push_str(&shell_quote(arg));
+                        }
+                        writeln!(std::io::stdout(), "{}", line)?;
+                    }
+                }
+            }
+            continue;
+        }
+
+        let result = cmds.and_then(|cmds| spawn_launches(batch, &cmds));
+        let rule_index = rule.map(|rule| rule.index);
+        let urls = launched_uris(&config.browsers(), spec, batch);
+        for (uri, url) in batch.iter().zip(&urls) {
+            log_decision(&Decision {
+                input: uri,
+                url,
+                mode: rule.and_then(|rule| rule.mode.as_deref()),
+                rule: rule_index,
+                spec,
+                pinned: pin.is_some(),
+                result: match &result {
+                    Err(err) => format!("{:#}", err),
+                    _ => String::from("ok"),
+                },
+            });
+        }
+        match result {
+            Err(err) => failures.push(err),
+            _ => history.extend(
+                batch
+                    .iter()
+                    .map(|uri| HistoryEntry::new(uri, spec, rule_index)),
+            ),
+        }
+    }
+    if let Err(err) = record_history(&config.history, history) {
+        warn!("Cannot record history: {:#}", err);
+    }
+
+    // report all failures, the first one decides the exit code
+    let mut failures = failures.into_iter();
+    let first = failures.next();
+    for err in failures {
+        eprintln!("bro: {:#}", err);
+    }
+    match first {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Prints the browser specification and the rule every URL is routed to
+fn list_routes(uris: &[String]) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    let config_path = get_config_path()?;
+    let config = Config::load(&config_path)?;
+    let state = load_state();
+    if let Some(pin) = state.active_pin() {
+        for uri in uris {
+            writeln!(out, "{}\t{}\tpinned to {}", uri, pin.spec, pin)?;
+        }
+        return Ok(());
+    }
+    let compiled = compile_active_rules(&config, &config_path, &state)?;
+    for uri in uris {
+        match match_rule_by(&compiled, uri, config.match_strategy) {
+            Some(rule) => writeln!(out, "{}\t{}\t{}", uri, rule.browser, rule)?,
+            None => writeln!(out, "{}\t\tno rule matches", uri)?,
+        }
+    }
+    Ok(())
+}
+
+fn read_stdin() -> Result<String> {
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Replaces `-` with the URLs read from standard input, one per line
+fn expand_stdin(urls: Vec<String>) -> Result<Vec<String>> {
+    let mut ret: Vec<String> = Vec::new();
+    for url in urls {
+        if url != "-" {
+            ret.push(url);
+            continue;
+        }
+        ret.extend(
+            read_stdin()?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from),
+        );
+    }
+    Ok(ret)
+}
+
+fn extract(list: bool, files: &[PathBuf]) -> Result<()> {
+    let text = if files.is_empty() {
+        read_stdin()?
+    } else {
+        let mut text = String::new();
+        for file in files {
+            text.push_str(
+                &fs::read_to_string(file)
+                    .with_context(|| format!("cannot read {}", file.display()))?,
+            );
+            text.push('\n');
+        }
+        text
+    };
+
+    let uris = extract_uris(&text);
+    if uris.is_empty() {
+        warn!("No URLs found");
+        return Ok(());
+    }
+    if list {
+        list_routes(&uris)
+    } else {
+        open_uris(&uris, &OpenOptions::default())
+    }
+}
+
+fn list_history(grep: Option<&str>, since: Option<&str>, json: bool) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    let grep = grep.map(Regex::new).transpose()?;
+    let since = since.map(parse_since).transpose()?;
+    let history = load_history()?;
+    let total = history.len();
+    let entries: Vec<(usize, &HistoryEntry)> = history
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (total - index, entry))
+        .filter(|(_, entry)| {
+            grep.as_ref()
+                .is_none_or(|re| re.is_match(&entry.url) || re.is_match(&entry.spec))
+        })
+        .filter(|(_, entry)| {
+            since.is_none_or(|since| entry.time().is_some_and(|time| time >= since))
+        })
+        .collect();
+
+    if json {
+        let mut values: Vec<Value> = Vec::new();
+        for (number, entry) in entries {
+            let mut value = serde_json::to_value(entry)?;
+            value["number"] = Value::from(number);
+            values.push(value);
+        }
+        writeln!(out, "{}", serde_json::to_string_pretty(&values)?)?;
+        return Ok(());
+    }
+    for (number, entry) in entries {
+        writeln!(out, "{}\t{}\t{}\t{}", number, entry.ts, entry.spec, entry.url)?;
+    }
+    Ok(())
+}
+
+/// Opens a URL of the history with another browser specification, without
+/// going through the rules
+fn reopen(entry: &str, with: Option<&str>) -> Result<()> {
+    let entry = find_history_entry(&load_history()?, entry)?;
+    let spec = with.unwrap_or(&entry.spec);
+    let config_path = get_config_path()?;
+    let config = Config::load(&config_path)?;
+    let uris = [entry.url.clone()];
+    let result = launch_browser_command(&config.browsers(), spec, &uris)
+        .with_context(|| in_config(&config_path))
+        .and_then(|cmds| spawn_launches(&uris, &cmds));
+    let urls = launched_uris(&config.browsers(), spec, &uris);
+    log_decision(&Decision {
+        input: &entry.url,
+        url: &urls[0],
+        mode: None,
+        rule: None,
+        spec,
+        pinned: false,
+        result: match &result {
+            Err(err) => format!("{:#}", err),
+            _ => String::from("ok"),
+        },
+    });
+    result?;
+    let history = vec![HistoryEntry::new(&entry.url, spec, None)];
+    if let Err(err) = record_history(&config.history, history) {
+        warn!("Cannot record history: {:#}", err);
+    }
+    Ok(())
+}
+
+fn profile_json(browser: &Browser, profile: &Profile) -> Result<Value> {
+    let mut value = serde_json::to_value(profile)?;
+    value["spec"] = Value::from(browser_spec(browser, Some(profile)));
+    Ok(value)
+}
+
+fn browser_json(browser: &Browser, custom: bool) -> Result<Value> {
+    let mut value = serde_json::to_value(browser)?;
+    value["spec"] = Value::from(browser_spec(browser, None));
+    value["custom"] = Value::from(custom);
+    value["profiles"] = browser
+        .profiles
+        .iter()
+        .map(|profile| profile_json(browser, profile))
+        .collect::<Result<_>>()?;
+    Ok(value)
+}
+
+fn list_browsers(json: bool) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    let config = Config::load(&get_config_path()?)?;
+    if json {
+        let mut browsers: Vec<Value> = Vec::new();
+        for browser in &config.detected_browsers {
+            browsers.push(browser_json(browser, false)?);
+        }
+        for browser in &config.custom_browsers {
+            browsers.push(browser_json(browser, true)?);
+        }
+        writeln!(out, "{}", serde_json::to_string_pretty(&browsers)?)?;
+        return Ok(());
+    }
+    for browser in config.browsers() {
+        writeln!(out, "{}\t{}\t{}", browser.id, browser.name, browser.command)?;
+    }
+    Ok(())
+}
+
+fn list_profiles(browser_id: &str, json: bool) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    let config = Config::load(&get_config_path()?)?;
+    let browser = config
+        .browsers()
+        .into_iter()
+        .find(|b| b.id == browser_id)
+        .ok_or_else(|| BroError::UnknownBrowser(browser_id.to_string()))?;
+    if json {
+        let profiles = browser
+            .profiles
+            .iter()
+            .map(|profile| profile_json(&browser, profile))
+            .collect::<Result<Vec<_>>>()?;
+        writeln!(out, "{}", serde_json::to_string_pretty(&profiles)?)?;
+        return Ok(());
+    }
+    for profile in &browser.profiles {
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            browser_spec(&browser, Some(profile)),
+            profile.name,
+            profile.email
+        )?;
+    }
+    Ok(())
+}
+
+fn list_rules(json: bool) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    let config = Config::load(&get_config_path()?)?;
+    if !json {
+        let now = SystemTime::now();
+        for (index, rule) in config.rules.iter().enumerate() {
+            let status = if rule.disabled {
+                "\tdisabled"
+            } else if rule.is_expired_at(now).unwrap_or(false) {
+                "\texpired"
+            } else if !rule.is_active_at(now).unwrap_or(true) {
+                "\tnot active yet"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}{}",
+                index, rule.matcher, rule.pattern, rule.browser, status
+            )?;
+        }
+        return Ok(());
+    }
+
+    let mut rules: Vec<Value> = Vec::new();
+    for (index, rule) in config.rules.iter().enumerate() {
+        let mut value = serde_json::to_value(rule)?;
+        value["index"] = Value::from(index);
+        match compile_rule(index, rule) {
+            Err(err) => value["error"] = Value::from(format!("{:#}", err)),
+            compiled => {
+                let matcher = compiled?.matcher;
+                value["compiled"] = serde_json::json!({
+                    "kind": matcher.kind(),
+                    "expression": matcher.expression(),
+                });
+            }
+        }
+        rules.push(value);
+    }
+    writeln!(out, "{}", serde_json::to_string_pretty(&rules)?)?;
+    Ok(())
 }
 
-fn open_uri(uri: &str) -> Result<()> {
-    let contents = fs::read_to_string(get_config_path()?).expect("Config not found");
-    let mut config: Config = serde_json::from_str(&contents)?;
-    if config.detected_browsers.is_empty() || config.rules.is_empty() {
-        return Err(Error::msg(format!("Invalid config file")));
+/// Prints live values for completion scripts, nothing is printed when the
+/// config cannot be loaded
+fn complete(kind: ValueKind) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    if let ValueKind::Matchers = kind {
+        for matcher in MATCHERS {
+            writeln!(out, "{}", matcher)?;
+        }
+        return Ok(());
     }
+    let config = match Config::load(&get_config_path()?) {
+        Err(_) => return Ok(()),
+        config => config?,
+    };
+    for browser in config.browsers() {
+        writeln!(out, "{}", browser_spec(&browser, None))?;
+        if let ValueKind::Specs = kind {
+            for profile in &browser.profiles {
+                writeln!(out, "{}", browser_spec(&browser, Some(profile)))?;
+                if !profile.email.is_empty() {
+                    writeln!(out, "{}:@{}", browser.id, profile.email)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-    let compiled = compile_rules(&config.rules).unwrap();
-    let browser = match_rules(&compiled, uri).unwrap();
+/// Completes browser specifications, browser ids and rule matchers of bash,
+/// where the script of clap completes everything else
+const BASH_LIVE_VALUES: &str = r#"
+# browser ids, browser specifications and rule matchers of the current bro.json
+_bro_live() {
+    local line=${COMP_LINE:0:COMP_POINT} words=() args=() kind= cur prefix value i
+    read -ra words <<< "$line"
+    [[ -z $line || $line == *[[:space:]] ]] && words+=("")
+    cur=${words[${#words[@]}-1]}
+    for ((i = 1; i < ${#words[@]} - 1; i++)); do
+        case ${words[i]} in
+            --with|--grep|--since|--for|--at|--not-before|--expires-at) ((i++)) ;;
+            -*) ;;
+            *) args+=("${words[i]}") ;;
+        esac
+    done
+    if [[ ${words[${#words[@]}-2]} == --with ]]; then
+        kind=specs
+    elif [[ $cur == --with=* ]]; then
+        kind=specs cur=${cur#--with=}
+    elif [[ $cur != -* ]]; then
+        case "${#args[@]} ${args[*]:0:2}" in
+            "1 pin" | "4 rule add") kind=specs ;;
+            "1 profiles") kind=browsers ;;
+            "2 rule add") kind=matchers ;;
+        esac
+    fi
+    if [[ -z $kind ]]; then
+        _bro "$@"
+        return
+    fi
+    # bash replaces the text after the last : or = of the word
+    prefix=${cur%"${cur##*[:=]}"}
+    COMPREPLY=()
+    while IFS= read -r value; do
+        [[ $value == "$cur"* ]] && COMPREPLY+=("$(printf '%q' "${value#"$prefix"}")")
+    done < <(bro __complete "$kind" 2>/dev/null)
+}
+complete -F _bro_live -o bashdefault -o default bro
+"#;
 
-    // add custom browsers at back
-    config.detected_browsers.extend(config.custom_browsers);
-    let cmd = launch_browser_command(&config.detected_browsers, &browser, uri).unwrap();
-    println!("{} uses {:?}", uri, cmd);
-    spawn_shell_command(&cmd.0, &cmd.1)?;
-    // exit after opening uri
-    std::process::exit(0);
+/// Completes browser specifications, browser ids and rule matchers of zsh,
+/// and hands everything else to the function of clap, renamed to _bro_static
+const ZSH_LIVE_VALUES: &str = r#"
+# browser ids, browser specifications and rule matchers of the current bro.json
+functions[_bro_static]=$functions[_bro]
+_bro() {
+    local kind= i
+    local -a args values
+    for ((i = 2; i < CURRENT; i++)); do
+        case $words[i] in
+            (--with|--grep|--since|--for|--at|--not-before|--expires-at) ((i++)) ;;
+            (-*) ;;
+            (*) args+=($words[i]) ;;
+        esac
+    done
+    if [[ $words[CURRENT-1] == --with ]]; then
+        kind=specs
+    elif [[ $words[CURRENT] != -* ]]; then
+        case "$#args ${args[1,2]}" in
+            ("1 pin"|"4 rule add") kind=specs ;;
+            ("1 profiles") kind=browsers ;;
+            ("2 rule add") kind=matchers ;;
+        esac
+    fi
+    if [[ -z $kind ]]; then
+        _bro_static "$@"
+        return
+    fi
+    values=(${(f)"$(bro __complete $kind 2>/dev/null)"})
+    compadd -a values
+}
+"#;
+
+/// Completes browser specifications, browser ids and rule matchers of fish,
+/// in addition to the completions of clap
+const FISH_LIVE_VALUES: &str = r#"
+# browser ids, browser specifications and rule matchers of the current bro.json
+complete -c bro -n "__fish_seen_subcommand_from open reopen" -l with -x -a "(bro __complete specs)"
+complete -c bro -n "__fish_seen_subcommand_from pin; and __fish_is_nth_token 1" -f -a "(bro __complete specs)"
+complete -c bro -n "__fish_seen_subcommand_from profiles; and __fish_is_nth_token 1" -f -a "(bro __complete browsers)"
+complete -c bro -n "__fish_seen_subcommand_from add; and __fish_is_nth_token 2" -f -a "(bro __complete matchers)"
+complete -c bro -n "__fish_seen_subcommand_from add; and __fish_is_nth_token 4" -f -a "(bro __complete specs)"
+"#;
+
+/// Prints the completion script of clap, with live values for bash, zsh and fish
+fn completions(shell: Shell) -> Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "bro", &mut script);
+    let mut script = String::from_utf8(script)?;
+    match shell {
+        Shell::Bash => script.push_str(BASH_LIVE_VALUES),
+        Shell::Fish => script.push_str(FISH_LIVE_VALUES),
+        // _bro has to be replaced before the script runs it when zsh autoloads it
+        Shell::Zsh => match script.rfind("\nif [ \"$funcstack[1]\" = \"_bro\" ]") {
+            Some(index) => script.insert_str(index, ZSH_LIVE_VALUES),
+            None => script.push_str(ZSH_LIVE_VALUES),
+        },
+        _ => (),
+    }
+    std::io::stdout().lock().write_all(script.as_bytes())?;
     Ok(())
 }
 
-fn write_example_config() -> Result<()> {
-    let config: Config = Config {
-        detected_browsers: available_browsers().unwrap(),
-        custom_browsers: vec![],
-        rules: vec![
-            Rule::new("DOMAIN-SUFFIX", "contoso.com", "Google Chrome:Profile 1"),
-            Rule::new("FINAL", "", "Google Chrome:Default"),
-        ],
+/// Prints expired rules, and whether there are any
+fn print_expired_rules(config: &Config) -> bool {
+    let expired = config.expired_rules(SystemTime::now());
+    for (mode, index, rule) in &expired {
+        println!(
+            "{}rule #{} `{}` expired at {}",
+            mode.map(|mode| format!("mode {} ", mode)).unwrap_or_default(),
+            index,
+            rule,
+            rule.expires_at.as_deref().unwrap_or_default()
+        );
+    }
+    if expired.is_empty() {
+        println!("No expired rules");
+    }
+    !expired.is_empty()
+}
+
+/// Asks a yes or no question on standard input, no is the default
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Saves rules that have been validated, the config is replaced atomically
+fn save_rules(config: &Config, config_path: &Path) -> Result<()> {
+    if let Some(final_index) = config.final_rule_index() {
+        for (index, rule) in config.rules.iter().enumerate().skip(final_index + 1) {
+            if !rule.disabled {
+                warn!(
+                    "Rule #{} `{}` is after FINAL rule #{} and will never match",
+                    index, rule, final_index
+                );
+            }
+        }
+    }
+    config.save(config_path)
+}
+
+fn edit_rules(command: RuleCommands) -> Result<()> {
+    let config_path = get_config_path()?;
+    let mut config = Config::load(&config_path)?;
+    match command {
+        RuleCommands::List { json } => return list_rules(json),
+        RuleCommands::Add {
+            matcher,
+            pattern,
+            spec,
+            at,
+            not_before,
+            expires_at,
+        } => {
+            let mut rule = match spec {
+                Some(spec) => Rule::new(&matcher, &pattern, &spec),
+                None if matcher == "FINAL" => Rule::new(&matcher, "", &pattern),
+                None => Cli::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "a browser specification is required after the pattern",
+                    )
+                    .exit(),
+            };
+            rule.not_before = not_before;
+            rule.expires_at = expires_at;
+            let index = config
+                .insert_rule(at, rule)
+                .with_context(|| in_config(&config_path))?;
+            println!("Added rule #{} `{}`", index, config.rules[index]);
+        }
+        RuleCommands::Move { from, to } => {
+            config.move_rule(from, to)?;
+            println!("Moved rule `{}` to #{}", config.rules[to], to);
+        }
+        RuleCommands::Remove { index } => {
+            let rule = config.remove_rule(index)?;
+            println!("Removed rule #{} `{}`", index, rule);
+        }
+        RuleCommands::Enable { index } => {
+            config
+                .set_rule_disabled(index, false)
+                .with_context(|| in_config(&config_path))?;
+            println!("Enabled rule #{} `{}`", index, config.rules[index]);
+        }
+        RuleCommands::Disable { index } => {
+            config.set_rule_disabled(index, true)?;
+            println!("Disabled rule #{} `{}`", index, config.rules[index]);
+        }
+        RuleCommands::Expired => {
+            print_expired_rules(&config);
+            return Ok(());
+        }
+        RuleCommands::Prune { yes } => {
+            if !print_expired_rules(&config) {
+                return Ok(());
+            }
+            if !yes && !confirm("Remove these rules?")? {
+                return Ok(());
+            }
+            let count = config.remove_expired_rules(SystemTime::now())?;
+            println!("Removed {} expired rule(s)", count);
+        }
+    }
+    save_rules(&config, &config_path)
+}
+
+fn mode(command: Option<ModeCommands>) -> Result<()> {
+    let mut state = State::load()?;
+    match command {
+        Some(ModeCommands::Set { name }) => {
+            let config_path = get_config_path()?;
+            let config = Config::load(&config_path)?;
+            config
+                .check_mode(&name)
+                .with_context(|| in_config(&config_path))?;
+            println!("Switched to mode {}", name);
+            state.mode = Some(name);
+            state.save()
+        }
+        Some(ModeCommands::Clear) => {
+            if let Some(mode) = state.mode.take() {
+                println!("Left mode {}, only the base rules are matched", mode);
+            }
+            state.save()
+        }
+        None => {
+            let config = Config::load(&get_config_path()?)?;
+            for (name, rules) in &config.modes {
+                let active = state.mode.as_ref() == Some(name);
+                println!(
+                    "{}{}\t{} rule(s)",
+                    if active { "* " } else { "  " },
+                    name,
+                    rules.len()
+                );
+            }
+            match &state.mode {
+                Some(mode) if !config.modes.contains_key(mode) => {
+                    println!("Active mode {} is not defined in the config", mode)
+                }
+                Some(_) => (),
+                None => println!("No active mode, only the base rules are matched"),
+            }
+            Ok(())
+        }
+    }
+}
+
+fn pin(spec: Option<String>, duration: Option<humantime::Duration>) -> Result<()> {
+    let mut state = State::load()?;
+    let spec = match spec {
+        Some(spec) => spec,
+        None => {
+            match state.active_pin() {
+                Some(pin) => println!("URLs are pinned to {}", pin),
+                None => println!("No active pin, URLs are routed by the rules"),
+            }
+            return Ok(());
+        }
     };
-    let json = serde_json::to_string_pretty(&config).unwrap();
-    let mut file = fs::File::create(get_config_path()?)?;
-    file.write_all(json.as_bytes())?;
+    let config_path = get_config_path()?;
+    let config = Config::load(&config_path)?;
+    let uris = [String::from("https://example.com/")];
+    launch_browser_command(&config.browsers(), &spec, &uris)
+        .with_context(|| in_config(&config_path))?;
+    let pin = Pin::new(&spec, duration.map(Into::into));
+    println!("Pinned URLs to {}", pin);
+    state.pin = Some(pin);
+    state.save()
+}
 
+fn unpin() -> Result<()> {
+    let mut state = State::load()?;
+    match state.pin.take() {
+        Some(pin) if !pin.is_expired() => println!("Unpinned {}", pin.spec),
+        _ => println!("No active pin"),
+    }
+    state.save()
+}
+
+fn check_config() -> Result<()> {
+    let config_path = get_config_path()?;
+    let config = Config::load(&config_path)?;
+
+    let mut problems = 0;
+    for (index, rule) in config.rules.iter().enumerate() {
+        if rule.disabled {
+            continue;
+        }
+        if let Err(err) = config.check_rule(index) {
+            eprintln!("{:#}", err);
+            problems += 1;
+        }
+    }
+    for mode in config.modes.keys() {
+        if let Err(err) = config.check_mode(mode) {
+            eprintln!("{:#}", err);
+            problems += 1;
+        }
+    }
+    let expired = config.expired_rules(SystemTime::now()).len();
+    if expired > 0 {
+        eprintln!(
+            "{} rule(s) have expired, run `bro rule prune` to remove them",
+            expired
+        );
+    }
+    for browser in &config.detected_browsers {
+        if !command_exists(&browser.command) {
+            eprintln!(
+                "browser {}: command {} does not exist, run `bro config --refresh`",
+                browser.id, browser.command
+            );
+        }
+    }
+
+    if problems > 0 {
+        return Err(BroError::InvalidConfig {
+            path: config_path,
+            reason: format!("{} problem(s) found", problems),
+        }
+        .into());
+    }
+    println!("{}: OK", config_path.display());
     Ok(())
 }
 
-const FORMAT_SPEC: &str = r#"Usage:
+fn doctor(json: bool) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+    let diagnosis = diagnose()?;
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&diagnosis)?)?;
+    } else {
+        write!(out, "{}", diagnosis)?;
+    }
+    match diagnosis.problems() {
+        0 => Ok(()),
+        problems => Err(BroError::ProblemsFound(problems).into()),
+    }
+}
+
+fn write_example_config(config_path: &Path) -> Result<()> {
+    Config::example()?.save(config_path)?;
+    println!("Creating example config at {}", config_path.display());
+    Ok(())
+}
 
-bro              Show this help message
-bro --register   Register as default browser
-bro --unregister Unregister as default browser
-bro <url>        Open URL in your desired browser
+fn config(init: bool, refresh: bool) -> Result<()> {
+    let config_path = get_config_path()?;
+    if init {
+        if config_path.exists() {
+            println!("Config {} already exists", config_path.display());
+            return Ok(());
+        }
+        return write_example_config(&config_path);
+    }
+    if refresh {
+        let mut config = Config::load(&config_path)?;
+        for warning in config.refresh_detected_browsers()? {
+            eprintln!("{}: {}", warning.source, warning.message);
+        }
+        config.save(&config_path)?;
+        println!(
+            "Detected {} browsers, saved into {}",
+            config.detected_browsers.len(),
+            config_path.display()
+        );
+        return Ok(());
+    }
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Config: {}\n", config_path.display())?;
+    writeln!(out, "{}", FORMAT_SPEC)?;
+    Ok(())
+}
 
-bro.json specification:
+const FORMAT_SPEC: &str = r#"bro.json specification:
 
-detected_browsers section contains all browser that Bro has detected, this section must not be changed, otherwise all changes will be lost after new launch of Bro settings.
+detected_browsers section contains all browser that Bro has detected, this section must not be changed, otherwise all changes will be lost after `bro config --refresh`.
 
 custom_browsers section contains all browser that's defined by user, the format is the same as detected_browsers.
+a browser may have args, which are passed to its command, profile arguments and URLs are inserted at the field code %u, %U, %f or %F of args (%f and %F take local paths of file URLs), or appended when there is none, and %% is a literal %.
+with %u or %f the browser is launched once per URL, with %U, %F or no field code once for all URLs, and profile arguments go before a -- or --untrusted-args right before the field code.
+on Linux, command and args of detected browsers come from the Exec line of their desktop files, e.g. command "flatpak" and args ["run", "org.mozilla.firefox", "%u"].
 
-rules contains rules for matching browser, rule are matched from top to down, and every rule can contain the following columns:
+rules contains rules for matching browser, rule are matched from top to down (see match_strategy), and every rule can contain the following columns:
 1. when matcher is WILDCARD or REGEX, matching pattern against the full URL (using wildcard or regular expression syntax)
 e.g. WILDCARD pattern "*://*.google.*" will match "http://www.google.com/", "https://www.google.co.uk/", "https://wtf.google.another.site/", "https://another.site/some.google.pdf"
 2. when matcher is DOMAIN, matching URL that domain is strictly the same as the pattern
@@ -73,51 +1094,141 @@ e.g. DOMAIN-WILDCARD "*.google.*" will match "http://www.google.com/", "https://
 5. when matcher is FINAL, pattern is ignored. It's the default rule that no rule above has matched the URL
 
 browser syntax: <browser.id>:<profile.id> (specifying profile) or <browser.id> (just launch the browser)
+a profile can also be specified by the email of its signed-in account or by its name, which stay the same across machines: <browser.id>:@<email> or <browser.id>:name=<profile.name>
+
+match_strategy is optional, with "first-match" (the default) the first matching rule from top to down wins, with "most-specific" the most specific matching rule wins regardless of its position:
+DOMAIN beats DOMAIN-SUFFIX, a DOMAIN-SUFFIX pattern with more labels beats one with fewer, then WILDCARD or REGEX rules that constrain the URL path, then other WILDCARD, REGEX and DOMAIN-WILDCARD rules, then FINAL.
+Equally specific rules are matched from top to down, and rules of the active mode beat base rules, except a FINAL rule of the mode, which only replaces the base FINAL rule. `bro -vv` logs the score of every matching rule.
+
+modes section is optional and maps mode names to lists of rules in the same format as rules, rules of the mode activated by `bro mode set <name>` are matched before the base rules, except a FINAL rule of the mode, which replaces the FINAL rule of the base rules
+
+a rule with "not_before" is not matched before that time, and a rule with "expires_at" is not matched from that time on, times are RFC 3339 like "2024-01-31T08:00:00Z" or dates like "2024-01-31" (midnight UTC), `bro rule prune` removes expired rules
 
+a rule with "disabled": true is kept but never matched, `bro rule` adds, moves, removes, enables and disables rules after validating them
+
+log section is optional and configures the log file, every opened URL is written as a JSON line with its rule, browser specification and result:
+enabled: whether the log file is written, false by default
+level: the lowest level written besides open decisions (error, warn, info, debug or trace), info by default
+path: the log file, bro.log in $XDG_STATE_HOME/bro (%LOCALAPPDATA%\bro on Windows) by default
+max_size: size in bytes after which the log file is rotated, 1048576 by default
+max_files: how many rotated files (bro.log.1, bro.log.2, ...) are kept, 3 by default
+
+history section is optional and configures the history of opened URLs, history.jsonl in the same directory as the log file:
+enabled: whether opened URLs are recorded, true by default
+max_entries: how many entries are kept, 1000 by default
+max_age: entries older than this duration (e.g. 30d or 12h) are dropped, 90d by default, an empty string keeps them forever
+
+profile_hints section is optional and tells `bro config --refresh` where to find profiles of browsers, a hint replaces the built-in hint with the same exe_path:
+exe_path: desktop file name (Linux), application name (macOS) or executable path (Windows) of the browser, * and ? match any characters
+profiles_path: directory containing the profiles, ~ is the home directory
+kind: chromium (profile names are read from Local State), gecko (profiles are read from profiles.ini), single (the profiles directory is the only profile) or generic, generic by default
+marker: file that a profile directory contains, History for chromium and cookies.sqlite for gecko by default, every directory is a profile when empty
+profile_args: arguments that select a profile, {id}, {name} and {path} are replaced with the profile directory name, name and path, ["--profile-directory={id}"] for chromium and ["-P", "{name}"] for gecko by default
+private_arg and private_name: argument and name of the __PRIVATE__ profile, which is only added when private_arg is set
+icon_path: icon file in a profile directory
+
+search_dirs is optional and lists directories that `bro config --refresh` scans on Linux for browsers without a desktop entry, ["~/Applications"] by default and [] to scan none.
+A directory in them (or one of them) is a browser when it is an extracted tarball of Tor Browser or Mullvad Browser (start-tor-browser or start-mullvad-browser) or of a Mozilla based browser (application.ini next to its executable), and a *.AppImage file is a browser when its embedded desktop entry handles http or https (read with unsquashfs) or its file name starts with a known browser like LibreWolf.
 "#;
 
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Some(Commands::Open {
+            urls,
+            with,
+            private,
+            print,
+        }) => open_uris(
+            &expand_stdin(urls)?,
+            &OpenOptions {
+                with,
+                private,
+                print,
+            },
+        ),
+        Some(Commands::Extract { list, files }) => extract(list, &files),
+        Some(Commands::History {
+            grep,
+            since,
+            json,
+            clear,
+        }) => {
+            if clear {
+                return clear_history();
+            }
+            list_history(grep.as_deref(), since.as_deref(), json)
+        }
+        Some(Commands::Reopen { entry, with }) => reopen(&entry, with.as_deref()),
+        Some(Commands::Register) => {
+            register()?;
+            set_default_browser()
+        }
+        Some(Commands::Unregister) => unregister(),
+        Some(Commands::Browsers { json }) => list_browsers(json),
+        Some(Commands::Profiles { browser, json }) => list_profiles(&browser, json),
+        Some(Commands::Rules { json }) => list_rules(json),
+        Some(Commands::Rule(command)) => edit_rules(command),
+        Some(Commands::Mode { command }) => mode(command),
+        Some(Commands::Pin { spec, duration }) => pin(spec, duration),
+        Some(Commands::Unpin) => unpin(),
+        Some(Commands::Check) => check_config(),
+        Some(Commands::Doctor { json }) => doctor(json),
+        Some(Commands::Completions { shell }) => completions(shell),
+        Some(Commands::Complete { kind }) => complete(kind),
+        Some(Commands::Config { init, refresh }) => config(init, refresh),
+        None => {
+            #[cfg(target_os = "macos")]
+            if let Some(uri) = macos_init() {
+                return open_uris(&[uri], &OpenOptions::default());
+            }
+
+            let config_path = get_config_path()?;
+            if !config_path.exists() {
+                write_example_config(&config_path)?;
+            }
+            Cli::command().print_help()?;
+            Ok(())
+        }
+    }
+}
+
+/// Keeps `bro <url>...`, `bro --register` and `bro --unregister` of older
+/// versions working, as they may still be used by existing registrations
+fn legacy_args(mut args: Vec<OsString>) -> Vec<OsString> {
+    match args.get(1).and_then(|arg| arg.to_str()) {
+        Some("--register") => args[1] = OsString::from("register"),
+        Some("--unregister") => args[1] = OsString::from("unregister"),
+        Some(arg) if is_uri(arg) => args.insert(1, OsString::from("open")),
+        _ => (),
+    }
+    args
+}
+
 fn main() {
-    env_logger::init();
-    // println!("Current default browser: {}", current_default_browser().unwrap());
-    // println!("Detected browsers: {:?}", available_browsers().unwrap());
-    // set_default_browser().unwrap();
-
-    // write_example_config().unwrap();
-
-    // let uris = [
-    //     "https://www.google.com/",
-    //     "https://github.com/",
-    // ];
-    // for uri in uris.iter() {
-    //     open_uri(uri).unwrap();
-    // }
-    let mut argv: Vec<String> = env::args().collect();
-    if argv.len() == 2 {
-        if argv[1] == "--register" {
-            register().unwrap();
-            set_default_browser().unwrap();
-        } else if argv[1] == "--unregister" {
-            unregister().unwrap();
-        } else {
-            open_uri(&argv[1]).unwrap();
+    let cli = Cli::parse_from(legacy_args(env::args_os().collect()));
+    let stderr_level = match cli.verbose {
+        0 => None,
+        1 => Some(LevelFilter::Info),
+        2 => Some(LevelFilter::Debug),
+        _ => Some(LevelFilter::Trace),
+    };
+    let log_config = get_config_path()
+        .map(|path| LogConfig::load(&path))
+        .unwrap_or_default();
+    init_logger(stderr_level, &log_config);
+    if let Err(err) = run(cli) {
+        // the reader of the output went away, like `bro rules | head -1`
+        if is_broken_pipe(&err) {
+            std::process::exit(EXIT_OK);
         }
-    } else {
-        #[cfg(target_os = "macos")]
-        match macos_init() {
-            Some(uri) => open_uri(&uri).unwrap(),
-            None => ()
-        }
-
-        let config_name = get_config_path().unwrap();
-        if !fs::metadata(&config_name).is_ok() {
-            write_example_config().unwrap();
-            println!(
-                "Creating example config at {}",
-                config_name.to_str().unwrap()
-            );
+        // the report of bro doctor lists the problems already
+        if !matches!(
+            err.downcast_ref::<BroError>(),
+            Some(BroError::ProblemsFound(_))
+        ) {
+            eprintln!("bro: {:#}", err);
         }
-        println!("Args: {:?}", argv);
-        println!("Usage: {} URL", argv[0]);
-        println!("{}", FORMAT_SPEC);
+        std::process::exit(exit_code(&err));
     }
+    std::process::exit(EXIT_OK);
 }
@@ -1,6 +1,21 @@
+use crate::error::*;
 use crate::types::*;
+use crate::utils::*;
 use crate::*;
-use log::trace;
+use log::{debug, trace};
+use std::cmp::Reverse;
+use std::fmt;
+use std::time::SystemTime;
+
+/// Names of all supported matchers
+pub const MATCHERS: &[&str] = &[
+    "WILDCARD",
+    "REGEX",
+    "DOMAIN-WILDCARD",
+    "DOMAIN",
+    "DOMAIN-SUFFIX",
+    "FINAL",
+];
 
 impl Rule {
     pub fn new(matcher: &str, pattern: &str, browser: &str) -> Rule {
@@ -8,80 +23,258 @@ impl Rule {
             matcher: String::from(matcher),
             pattern: String::from(pattern),
             browser: String::from(browser),
+            disabled: false,
+            not_before: None,
+            expires_at: None,
+        }
+    }
+
+    fn times(&self) -> Result<(Option<SystemTime>, Option<SystemTime>)> {
+        let not_before = self.not_before.as_deref().map(parse_time).transpose()?;
+        let expires_at = self.expires_at.as_deref().map(parse_time).transpose()?;
+        Ok((not_before, expires_at))
+    }
+
+    pub fn is_expired_at(&self, time: SystemTime) -> Result<bool> {
+        let (_, expires_at) = self.times()?;
+        Ok(expires_at.is_some_and(|expires_at| expires_at <= time))
+    }
+
+    /// Whether the time is between not_before and expires_at of the rule
+    pub fn is_active_at(&self, time: SystemTime) -> Result<bool> {
+        let (not_before, expires_at) = self.times()?;
+        Ok(not_before.is_none_or(|not_before| not_before <= time)
+            && expires_at.is_none_or(|expires_at| time < expires_at))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pattern.is_empty() {
+            write!(f, "{},{}", self.matcher, self.browser)
+        } else {
+            write!(f, "{},{},{}", self.matcher, self.pattern, self.browser)
         }
     }
 }
 
-fn compile_rule(rule: &Rule) -> Result<CompiledRule> {
+impl fmt::Display for CompiledRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(mode) = &self.mode {
+            write!(f, "mode {} ", mode)?;
+        }
+        write!(f, "rule #{} `{}`", self.index, self.rule)
+    }
+}
+
+impl CompiledMatcher {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CompiledMatcher::Wildcard(_) => "wildcard",
+            CompiledMatcher::Regex(_) => "regex",
+        }
+    }
+
+    /// The wildcard or regular expression that URLs are matched against
+    pub fn expression(&self) -> String {
+        match self {
+            CompiledMatcher::Wildcard(w) => w.to_string(),
+            CompiledMatcher::Regex(r) => r.as_str().to_string(),
+        }
+    }
+}
+
+fn compile_matcher(rule: &Rule) -> Result<CompiledMatcher> {
     match rule.matcher.as_str() {
-        "WILDCARD" => Ok(CompiledRule {
-            matcher: CompiledMatcher::Wildcard(WildMatch::new(&rule.pattern)),
-            browser: rule.browser.clone(),
-            rule: rule.clone(),
-        }),
-        "REGEX" => Ok(CompiledRule {
-            matcher: CompiledMatcher::Regex(Regex::new(&rule.pattern)?),
-            browser: rule.browser.clone(),
-            rule: rule.clone(),
-        }),
-        "DOMAIN-WILDCARD" => Ok(CompiledRule {
-            matcher: CompiledMatcher::Wildcard(WildMatch::new(&format!(
-                "http?://{}/*",
-                rule.pattern
-            ))),
-            browser: rule.browser.clone(),
-            rule: rule.clone(),
-        }),
-        "DOMAIN" => Ok(CompiledRule {
-            matcher: CompiledMatcher::Regex(Regex::new(&format!(
-                r"http[s]?://{}/.*",
-                rule.pattern.replace(".", r"\.")
-            ))?),
-            browser: rule.browser.clone(),
-            rule: rule.clone(),
-        }),
-        "DOMAIN-SUFFIX" => Ok(CompiledRule {
-            matcher: CompiledMatcher::Regex(Regex::new(&format!(
-                r"http[s]?://(.+\.)?{}/.*",
-                rule.pattern.replace(".", r"\.")
-            ))?),
-            browser: rule.browser.clone(),
-            rule: rule.clone(),
-        }),
-        "FINAL" => Ok(CompiledRule {
-            matcher: CompiledMatcher::Wildcard(WildMatch::new("*")),
-            browser: rule.browser.clone(),
-            rule: rule.clone(),
-        }),
-        other => return Err(Error::msg(format!("Unknown rule type {}", other))),
+        "WILDCARD" => Ok(CompiledMatcher::Wildcard(WildMatch::new(&rule.pattern))),
+        "REGEX" => Ok(CompiledMatcher::Regex(Regex::new(&rule.pattern)?)),
+        "DOMAIN-WILDCARD" => Ok(CompiledMatcher::Wildcard(WildMatch::new(&format!(
+            "http?://{}/*",
+            rule.pattern
+        )))),
+        "DOMAIN" => Ok(CompiledMatcher::Regex(Regex::new(&format!(
+            r"http[s]?://{}/.*",
+            rule.pattern.replace(".", r"\.")
+        ))?)),
+        "DOMAIN-SUFFIX" => Ok(CompiledMatcher::Regex(Regex::new(&format!(
+            r"http[s]?://(.+\.)?{}/.*",
+            rule.pattern.replace(".", r"\.")
+        ))?)),
+        "FINAL" => Ok(CompiledMatcher::Wildcard(WildMatch::new("*"))),
+        other => Err(Error::msg(format!("unknown matcher {}", other))),
     }
 }
 
-pub fn compile_rules(rules: &Vec<Rule>) -> Result<Vec<CompiledRule>> {
+pub fn compile_rule(index: usize, rule: &Rule) -> Result<CompiledRule> {
+    let matcher = rule
+        .times()
+        .and_then(|_| compile_matcher(rule))
+        .map_err(|e| BroError::InvalidRule {
+        index,
+        rule: rule.clone(),
+        reason: e.to_string(),
+    })?;
+    Ok(CompiledRule {
+        index,
+        mode: None,
+        matcher,
+        browser: rule.browser.clone(),
+        rule: rule.clone(),
+    })
+}
+
+/// Compiles rules that are enabled and active now
+pub fn compile_rules(rules: &[Rule]) -> Result<Vec<CompiledRule>> {
+    let now = SystemTime::now();
     let mut ret: Vec<CompiledRule> = Vec::new();
-    for rule in rules {
-        ret.push(compile_rule(rule)?);
+    for (index, rule) in rules.iter().enumerate() {
+        if rule.disabled {
+            trace!("Skipped disabled rule #{} {}", index, rule);
+            continue;
+        }
+        let compiled = compile_rule(index, rule)?;
+        if !rule.is_active_at(now)? {
+            trace!("Skipped inactive rule #{} {}", index, rule);
+            continue;
+        }
+        ret.push(compiled);
     }
     Ok(ret)
 }
 
-pub fn match_rules(rules: &Vec<CompiledRule>, uri: &str) -> Result<String> {
+/// Returns the first rule that matches the URL
+pub fn match_rule<'a>(rules: &'a [CompiledRule], uri: &str) -> Option<&'a CompiledRule> {
     for rule in rules {
         match &rule.matcher {
             CompiledMatcher::Wildcard(w) => {
                 if w.matches(uri) {
-                    trace!("{} Matched regex rule {:?}", uri, rule.rule);
-                    return Ok(rule.browser.clone());
+                    trace!("{} Matched wildcard {}", uri, rule);
+                    return Some(rule);
                 }
             }
             CompiledMatcher::Regex(r) => {
                 if r.is_match(uri) {
-                    trace!("{} Matched wildcard rule {:?}", uri, rule.rule);
-                    return Ok(rule.browser.clone());
+                    trace!("{} Matched regex {}", uri, rule);
+                    return Some(rule);
                 }
             }
         }
     }
     trace!("{} fallbacked to default rule", uri);
-    Ok(String::new()) // fallback to default rule
+    None
+}
+
+/// How specific a rule is under the most-specific match strategy, rules with a
+/// higher tier win, then domain patterns with more labels within the tier,
+/// then the rule that comes first
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct Specificity {
+    /// Rules of the active mode always beat base rules, except FINAL rules of
+    /// the mode, which replace the base FINAL rule that they are compiled before
+    pub mode: bool,
+    pub tier: u8,
+    /// Labels of DOMAIN and DOMAIN-SUFFIX patterns, 0 for other matchers
+    pub labels: usize,
+    /// Position of the rule among the rules of the mode and the base rules
+    pub position: Reverse<usize>,
+}
+
+impl fmt::Display for Specificity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tier = match self.tier {
+            5 => "exact domain",
+            4 => "domain suffix",
+            3 => "path-constrained pattern",
+            2 => "pattern",
+            _ => "final",
+        };
+        write!(f, "tier {} ({})", self.tier, tier)?;
+        if self.labels > 0 {
+            write!(f, ", {} labels", self.labels)?;
+        }
+        if self.mode {
+            write!(f, ", mode rule")?;
+        }
+        write!(f, ", position {}", self.position.0)
+    }
+}
+
+/// Whether a WILDCARD or REGEX pattern constrains the URL path, i.e. there is
+/// more than a wildcard after the first slash that follows the host
+fn constrains_path(pattern: &str) -> bool {
+    let rest = pattern.split_once("://").map_or(pattern, |(_, rest)| rest);
+    match rest.split_once('/') {
+        Some((_, path)) => !path
+            .replace(".*", "")
+            .trim_matches(|c| matches!(c, '*' | '$' | '?'))
+            .is_empty(),
+        None => false,
+    }
+}
+
+impl CompiledRule {
+    pub fn matches(&self, uri: &str) -> bool {
+        match &self.matcher {
+            CompiledMatcher::Wildcard(w) => w.matches(uri),
+            CompiledMatcher::Regex(r) => r.is_match(uri),
+        }
+    }
+
+    /// Specificity of the rule at the position of the compiled rules
+    pub fn specificity(&self, position: usize) -> Specificity {
+        let pattern = &self.rule.pattern;
+        let tier = match self.rule.matcher.as_str() {
+            "DOMAIN" => 5,
+            "DOMAIN-SUFFIX" => 4,
+            "WILDCARD" | "REGEX" if constrains_path(pattern) => 3,
+            "FINAL" => 0,
+            _ => 2,
+        };
+        Specificity {
+            mode: self.mode.is_some() && tier > 0,
+            tier,
+            // a.example.com is more specific than example.com, while longer
+            // WILDCARD or REGEX patterns are not
+            labels: match tier {
+                4 | 5 => pattern.trim_matches('.').split('.').count(),
+                _ => 0,
+            },
+            position: Reverse(position),
+        }
+    }
+}
+
+/// Returns the most specific rule that matches the URL, the first one of
+/// otherwise equally specific rules
+pub fn match_most_specific<'a>(
+    rules: &'a [CompiledRule],
+    uri: &str,
+) -> Option<&'a CompiledRule> {
+    let mut best: Option<(Specificity, &CompiledRule)> = None;
+    for (position, rule) in rules.iter().enumerate() {
+        if !rule.matches(uri) {
+            continue;
+        }
+        let specificity = rule.specificity(position);
+        debug!("{} matched {}: {}", uri, rule, specificity);
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, rule));
+        }
+    }
+    if let Some((specificity, rule)) = best {
+        debug!("{} chose {}: {}", uri, rule, specificity);
+    }
+    best.map(|(_, rule)| rule)
+}
+
+/// Returns the rule that opens the URL according to the match strategy
+pub fn match_rule_by<'a>(
+    rules: &'a [CompiledRule],
+    uri: &str,
+    strategy: MatchStrategy,
+) -> Option<&'a CompiledRule> {
+    match strategy {
+        MatchStrategy::FirstMatch => match_rule(rules, uri),
+        MatchStrategy::MostSpecific => match_most_specific(rules, uri),
+    }
 }
@@ -0,0 +1,66 @@
+use crate::types::*;
+use crate::utils::*;
+use crate::*;
+use std::fmt;
+use std::io::ErrorKind;
+use std::time::{Duration, SystemTime};
+
+fn state_path() -> Result<PathBuf> {
+    let mut path = get_state_dir()?;
+    path.push("state.json");
+    Ok(path)
+}
+
+impl State {
+    /// Reads state.json, a missing file is an empty state
+    pub fn load() -> Result<State> {
+        let path = state_path()?;
+        let contents = match fs::read_to_string(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(State::default()),
+            other => other?,
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| Error::msg(format!("invalid state {}: {}", path.display(), e)))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomically(&state_path()?, json.as_bytes())
+    }
+}
+
+impl State {
+    /// The pin, unless it has expired
+    pub fn active_pin(&self) -> Option<&Pin> {
+        self.pin.as_ref().filter(|pin| !pin.is_expired())
+    }
+}
+
+impl Pin {
+    pub fn new(spec: &str, duration: Option<Duration>) -> Pin {
+        Pin {
+            spec: spec.to_string(),
+            expires_at: duration.map(|duration| {
+                humantime::format_rfc3339_seconds(SystemTime::now() + duration).to_string()
+            }),
+        }
+    }
+
+    /// Pins with an expiry that cannot be parsed are treated as expired
+    pub fn is_expired(&self) -> bool {
+        match &self.expires_at {
+            Some(expires_at) => humantime::parse_rfc3339_weak(expires_at)
+                .map_or(true, |expires_at| expires_at <= SystemTime::now()),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.expires_at {
+            Some(expires_at) => write!(f, "{} until {}", self.spec, expires_at),
+            None => write!(f, "{} until `bro unpin`", self.spec),
+        }
+    }
+}
@@ -1,27 +1,111 @@
 use crate::*;
+use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Profile {
     pub id: String,
     pub name: String,
     pub args: Vec<String>,
     pub icon_path: String,
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub path: String,
+    /// Email of the signed-in account
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub email: String,
+    /// Avatar of the profile, e.g. chrome://theme/IDR_PROFILE_AVATAR_26
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub avatar: String,
+    /// Whether the profile is managed by an organization
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub managed: bool,
+    /// Whether the browser opens this profile when no profile is given
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub default: bool,
+    /// Firefox container that URLs are opened in, through the Open URL in
+    /// Container extension
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub container: String,
+}
+
+/// Metadata that a browser keeps about one profile besides its directory
+#[derive(Debug, Default, Clone)]
+pub struct ProfileMetadata {
+    pub name: String,
+    pub email: String,
+    pub avatar: String,
+    pub managed: bool,
+}
+
+/// How a browser lays out profiles in its profiles directory
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HintKind {
+    /// Profile directories named in `Local State`, like Chrome and Edge
+    Chromium,
+    /// Profiles registered in profiles.ini, with containers, like Firefox
+    Gecko,
+    /// Every directory that contains the marker file is a profile
+    #[default]
+    Generic,
+    /// The profiles directory is the only profile, like Opera on Linux
+    Single,
 }
 
+/// Where a browser keeps its profiles and how it is launched with one. Hints
+/// in the config override built-in hints with the same exe_path
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProfileHint {
-    pub exe_path: &'static str,
-    pub profiles_path: &'static str,
-    pub private_arg: &'static str,
-    pub private_name: &'static str,
-    pub profile_arg: &'static str,
-    pub profile_arg_ctor: fn(&ProfileHint, &str) -> Vec<String>,
-    pub icon_path: &'static str,
-    pub detector: fn(&str, &str) -> Result<bool>,
+    /// Desktop file name (Linux), application name (macOS) or executable path
+    /// (Windows), `*` and `?` match any characters
+    pub exe_path: String,
+    pub profiles_path: String,
+    #[serde(default)]
+    pub kind: HintKind,
+    /// File that a profile directory contains, `History` for Chromium and
+    /// `cookies.sqlite` for Gecko when empty
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub marker: String,
+    /// Arguments that select a profile, `{id}`, `{name}` and `{path}` are
+    /// replaced with the profile directory name, the profile name and the
+    /// profile path. Defaults to `--profile-directory={id}` for Chromium and
+    /// `-P {name}` for Gecko
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub profile_args: Vec<String>,
+    /// No private profile is listed when empty
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub private_arg: String,
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub private_name: String,
+    /// Icon file in a profile directory
+    #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
+    pub icon_path: String,
 }
 
 impl ProfileHint {
-    pub fn construct_profile_arguments(self: &ProfileHint, name: &str) -> Vec<String> {
-        (self.profile_arg_ctor)(self, name)
+    pub fn marker(&self) -> &str {
+        match (self.marker.as_str(), self.kind) {
+            ("", HintKind::Chromium) => "History",
+            ("", HintKind::Gecko) => "cookies.sqlite",
+            (marker, _) => marker,
+        }
+    }
+
+    pub fn construct_profile_arguments(&self, id: &str, name: &str, path: &str) -> Vec<String> {
+        let template: &[&str] = match (self.profile_args.is_empty(), self.kind) {
+            (true, HintKind::Chromium) => &["--profile-directory={id}"],
+            (true, HintKind::Gecko) => &["-P", "{name}"],
+            _ => &[],
+        };
+        let args = template
+            .iter()
+            .copied()
+            .chain(self.profile_args.iter().map(String::as_str));
+        args.map(|arg| {
+            arg.replace("{id}", id)
+                .replace("{name}", name)
+                .replace("{path}", path)
+        })
+        .collect()
     }
 }
 
@@ -30,15 +114,116 @@ pub struct Browser {
     pub id: String,
     pub name: String,
     pub command: String,
+    /// Arguments of the command before profile arguments and URLs, like
+    /// `run org.mozilla.firefox` of `flatpak`
+    #[serde(skip_serializing_if = "Vec::is_empty", default = "Vec::new")]
+    pub args: Vec<String>,
     #[serde(skip_serializing_if = "Vec::is_empty", default = "Vec::new")]
     pub profiles: Vec<Profile>,
 }
 
+/// Sections of the config that are left out when saving it, so that editing
+/// rules does not add them to a config that is written by hand
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
 #[derive(Serialize, Deserialize, Debug)]
-struct Config {
+pub struct Config {
     pub detected_browsers: Vec<Browser>,
     pub custom_browsers: Vec<Browser>,
     pub rules: Vec<Rule>,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub match_strategy: MatchStrategy,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub log: LogConfig,
+    #[serde(skip_serializing_if = "is_default", default)]
+    pub history: HistoryConfig,
+    /// Named rule groups, rules of the active mode are matched before the base rules
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub modes: BTreeMap<String, Vec<Rule>>,
+    /// Profile hints used by detection in addition to the built-in ones
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub profile_hints: Vec<ProfileHint>,
+    /// Directories scanned for AppImages and extracted tarballs of browsers,
+    /// ~/Applications when not set
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub search_dirs: Option<Vec<String>>,
+}
+
+/// What browser detection uses besides the installed browsers
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub hints: Vec<ProfileHint>,
+    /// Directories scanned for browsers without a desktop entry, Linux only
+    pub search_dirs: Vec<String>,
+}
+
+/// Browsers found by detection, and what went wrong while detecting them
+#[derive(Debug, Default)]
+pub struct DetectedBrowsers {
+    pub browsers: Vec<Browser>,
+    pub warnings: Vec<DetectionWarning>,
+}
+
+/// A browser, or a directory, that detection had to skip partly or entirely.
+/// A browser whose profiles cannot be detected is kept without profiles
+#[derive(Serialize, Debug, Clone)]
+pub struct DetectionWarning {
+    /// Browser id, or path of a search directory
+    pub source: String,
+    pub message: String,
+}
+
+/// Runtime state of bro, kept in state.json in the state directory
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct State {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<Pin>,
+}
+
+/// Browser specification that all URLs are opened with instead of the rules
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pin {
+    pub spec: String,
+    /// RFC 3339 time the pin expires at, None when it lasts until `bro unpin`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expires_at: Option<String>,
+}
+
+/// How the rule that opens a URL is chosen among the matching rules
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchStrategy {
+    /// The first matching rule from top to down
+    #[default]
+    FirstMatch,
+    /// The most specific matching rule, see Specificity
+    MostSpecific,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LogConfig {
+    pub enabled: bool,
+    pub level: String,
+    /// bro.log in the state directory when empty
+    pub path: String,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Oldest entries are dropped beyond this count
+    pub max_entries: usize,
+    /// Entries older than this duration are dropped, e.g. 30d, empty keeps them forever
+    pub max_age: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -47,6 +232,15 @@ pub struct Rule {
     #[serde(skip_serializing_if = "String::is_empty", default = "String::new")]
     pub pattern: String,
     pub browser: String,
+    /// Disabled rules are kept in the config but never matched
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub disabled: bool,
+    /// The rule is not matched before this time, RFC 3339 or YYYY-MM-DD
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub not_before: Option<String>,
+    /// The rule is not matched from this time on, RFC 3339 or YYYY-MM-DD
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expires_at: Option<String>,
 }
 
 #[derive(Debug)]
@@ -57,6 +251,9 @@ pub enum CompiledMatcher {
 
 #[derive(Debug)]
 pub struct CompiledRule {
+    pub index: usize,
+    /// Mode the rule belongs to, None for base rules
+    pub mode: Option<String>,
     pub rule: Rule,
     pub matcher: CompiledMatcher,
     pub browser: String,
@@ -1,6 +1,11 @@
 use crate::browser::*;
+use crate::desktop::*;
+use crate::discovery::*;
+use crate::ini::*;
 use crate::types::*;
 use crate::*;
+use anyhow::Context;
+use std::path::Path;
 
 // for Mime::from_str
 use std::ffi::OsString;
@@ -27,7 +32,7 @@ Name=bro
 GenericName=bro: a browser selector
 Comment=Redirects open URLs to a browser of your choice.
 Keywords=web;browser;internet;
-Exec={} %u
+Exec={} open %u
 StartupNotify=true
 Terminal=false
 Type=Application
@@ -61,31 +66,251 @@ pub fn unregister() -> Result<()> {
     Ok(())
 }
 
-pub fn available_browsers() -> Result<Vec<Browser>> {
+/// Whether an application can handle http or https
+fn is_browser_entry(e: &handlr_regex::DesktopEntry) -> bool {
+    e.mimes
+        .contains(&mime::Mime::from_str("x-scheme-handler/https").unwrap())
+        || e.mimes
+            .contains(&mime::Mime::from_str("x-scheme-handler/http").unwrap())
+}
+
+/// Browser of the desktop entry with the desktop file name id, without profiles
+fn desktop_entry_browser(id: String, e: &handlr_regex::DesktopEntry) -> Result<Browser> {
+    let path = find_desktop_file(&id);
+    let icon = path.as_deref().and_then(read_desktop_icon).unwrap_or_default();
+    let path = path.map(|path| path.to_string_lossy().to_string());
+    let keys = DesktopEntryKeys {
+        name: &e.name,
+        icon: &icon,
+        path: path.as_deref().unwrap_or_default(),
+    };
+    let (command, args) = desktop_entry_command(&e.exec, &keys).context("cannot parse Exec")?;
+    Ok(Browser {
+        id,
+        name: e.name.clone(),
+        command,
+        args,
+        profiles: Vec::new(),
+    })
+}
+
+pub fn available_browsers(detection: &Detection) -> Result<DetectedBrowsers> {
+    let mut warnings: Vec<DetectionWarning> = Vec::new();
     // list using handlr
     // filter browsers from applications that can handle http and https
     let mut browsers: Vec<Browser> = handlr_regex::apps::SystemApps::get_entries()?
         .map(|(_, e)| e)
-        .filter(|e| {
-            e.mimes
-                .contains(&mime::Mime::from_str("x-scheme-handler/https").unwrap())
-                || e.mimes
-                    .contains(&mime::Mime::from_str("x-scheme-handler/http").unwrap())
-        })
-        .map(|e| Browser {
-            // polish the content
-            id: e.file_name.into_string().expect("File name is invalid"),
-            name: e.name.clone(),
-            command: e.exec.clone(),
-            profiles: Vec::new(),
+        .filter(is_browser_entry)
+        .filter_map(|e| {
+            let id = match e.file_name.clone().into_string() {
+                Err(file_name) => {
+                    warnings.push(DetectionWarning {
+                        source: file_name.to_string_lossy().to_string(),
+                        message: String::from("desktop file name is not UTF-8"),
+                    });
+                    return None;
+                }
+                id => id.ok()?,
+            };
+            match desktop_entry_browser(id.clone(), &e) {
+                Err(err) => {
+                    warnings.push(DetectionWarning {
+                        source: id,
+                        message: format!("{:#}", err),
+                    });
+                    None
+                }
+                browser => browser.ok(),
+            }
         })
         .collect();
-    browsers.iter_mut().for_each(|b| {
-        get_profiles(b).unwrap();
-    });
-    Ok(browsers)
+    let hints = sandboxed_hints(&detection.hints);
+    warnings.extend(detect_profiles_parallel(&mut browsers, |b| {
+        get_profiles(b, &hints)
+    }));
+    let discovered = discover_browsers(&detection.search_dirs, &detection.hints);
+    warnings.extend(discovered.warnings);
+    for browser in discovered.browsers {
+        // tarballs may also have a desktop entry of their own
+        if browsers
+            .iter()
+            .any(|b| b.id == browser.id || b.command == browser.command)
+        {
+            continue;
+        }
+        browsers.push(browser);
+    }
+    Ok(DetectedBrowsers { browsers, warnings })
 }
 
+/// Detects one browser again, from its desktop entry, or from the search
+/// directories for browsers without one, None when it is gone
+pub fn detect_browser(id: &str, detection: &Detection) -> Result<Option<Browser>> {
+    let entry = handlr_regex::apps::SystemApps::get_entries()?
+        .find(|(file_name, e)| file_name == id && is_browser_entry(e));
+    let Some((_, e)) = entry else {
+        // AppImages and tarballs are only found by scanning the search directories
+        if id.ends_with(".desktop") {
+            return Ok(None);
+        }
+        let discovered = discover_browsers(&detection.search_dirs, &detection.hints);
+        return Ok(discovered.browsers.into_iter().find(|b| b.id == id));
+    };
+    let mut browser = desktop_entry_browser(id.to_string(), &e)?;
+    get_profiles(&mut browser, &sandboxed_hints(&detection.hints))?;
+    Ok(Some(browser))
+}
+
+/// Desktop file names of Flatpak and Snap installs of browsers, the desktop
+/// file name of their native install, and their profiles directory. Flatpaks
+/// keep ~/.config in ~/.var/app/<app id>/config, Snaps keep their data in
+/// ~/snap/<name>
+const SANDBOXED_BROWSERS: &[(&str, &str, &str)] = &[
+    (
+        "org.mozilla.firefox.desktop",
+        "firefox.desktop",
+        "~/.var/app/org.mozilla.firefox/.mozilla/firefox",
+    ),
+    (
+        "com.google.Chrome.desktop",
+        "google-chrome.desktop",
+        "~/.var/app/com.google.Chrome/config/google-chrome",
+    ),
+    (
+        "com.google.ChromeDev.desktop",
+        "google-chrome-unstable.desktop",
+        "~/.var/app/com.google.ChromeDev/config/google-chrome-unstable",
+    ),
+    (
+        "org.chromium.Chromium.desktop",
+        "chromium.desktop",
+        "~/.var/app/org.chromium.Chromium/config/chromium",
+    ),
+    (
+        "com.microsoft.Edge.desktop",
+        "microsoft-edge.desktop",
+        "~/.var/app/com.microsoft.Edge/config/microsoft-edge",
+    ),
+    (
+        "com.brave.Browser.desktop",
+        "brave-browser.desktop",
+        "~/.var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser",
+    ),
+    (
+        "com.vivaldi.Vivaldi.desktop",
+        "vivaldi-stable.desktop",
+        "~/.var/app/com.vivaldi.Vivaldi/config/vivaldi",
+    ),
+    (
+        "com.opera.Opera.desktop",
+        "opera.desktop",
+        "~/.var/app/com.opera.Opera/config/opera",
+    ),
+    (
+        "io.gitlab.librewolf-community.desktop",
+        "librewolf.desktop",
+        "~/.var/app/io.gitlab.librewolf-community/.librewolf",
+    ),
+    (
+        "net.waterfox.waterfox.desktop",
+        "waterfox.desktop",
+        "~/.var/app/net.waterfox.waterfox/.waterfox",
+    ),
+    (
+        "one.ablaze.floorp.desktop",
+        "floorp.desktop",
+        "~/.var/app/one.ablaze.floorp/.floorp",
+    ),
+    (
+        "firefox_firefox.desktop",
+        "firefox.desktop",
+        "~/snap/firefox/common/.mozilla/firefox",
+    ),
+    (
+        "chromium_chromium.desktop",
+        "chromium.desktop",
+        "~/snap/chromium/common/chromium",
+    ),
+    (
+        "brave_brave.desktop",
+        "brave-browser.desktop",
+        "~/snap/brave/current/.config/BraveSoftware/Brave-Browser",
+    ),
+    (
+        "opera_opera.desktop",
+        "opera.desktop",
+        "~/snap/opera/current/.config/opera",
+    ),
+];
+
+/// Adds hints of Flatpak and Snap installs, copied from the hints of their
+/// native installs with the profiles directory in the sandbox
+pub fn sandboxed_hints(hints: &[ProfileHint]) -> Vec<ProfileHint> {
+    let mut ret = hints.to_vec();
+    for (id, native, profiles_path) in SANDBOXED_BROWSERS {
+        if let Some(hint) = hints
+            .iter()
+            .find(|hint| WildMatch::new(&hint.exe_path).matches(native))
+        {
+            ret.push(ProfileHint {
+                exe_path: id.to_string(),
+                profiles_path: profiles_path.to_string(),
+                ..hint.clone()
+            });
+        }
+    }
+    ret
+}
+
+/// Path of a desktop file in the applications directories of XDG_DATA_HOME
+/// and XDG_DATA_DIRS
+fn find_desktop_file(file_name: &str) -> Option<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    let data_dirs = match env::var_os("XDG_DATA_DIRS") {
+        Some(dirs) if !dirs.is_empty() => dirs,
+        _ => OsString::from("/usr/local/share:/usr/share"),
+    };
+    std::iter::once(data_home)
+        .chain(env::split_paths(&data_dirs))
+        .map(|dir| dir.join("applications").join(file_name))
+        .find(|path| path.is_file())
+}
+
+fn read_desktop_icon(path: &Path) -> Option<String> {
+    let sections = parse_ini(&fs::read_to_string(path).ok()?);
+    let entry = sections.iter().find(|s| s.name == "Desktop Entry")?;
+    entry.get("Icon").map(|icon| icon.to_string())
+}
+
+/// Program and arguments of the Exec value of a desktop entry. Flatpaks are
+/// run without file forwarding, which would take profile arguments before
+/// the URLs for files, and Snaps are run from /snap/bin without the env
+/// prefix that only sets a hint for the desktop
+pub fn desktop_entry_command(
+    exec: &str,
+    keys: &DesktopEntryKeys,
+) -> Result<(String, Vec<String>)> {
+    let mut args = parse_exec(exec, keys)?;
+    let program = Path::new(&args[0])
+        .file_name()
+        .unwrap_or_default()
+        .to_os_string();
+    if program == "flatpak" && args.get(1).is_some_and(|arg| arg == "run") {
+        args.retain(|arg| !arg.starts_with("@@") && arg != "--file-forwarding");
+    }
+    if program == "env" {
+        if let Some(snap) = args.iter().position(|arg| arg.starts_with("/snap/bin/")) {
+            args.drain(..snap);
+        }
+    }
+    let program = args.remove(0);
+    Ok((program, args))
+}
+
+
 pub fn expand_path(path: &str) -> Result<Vec<String>> {
     // expand HOME directory
     if path.contains("~") {
@@ -96,13 +321,8 @@ pub fn expand_path(path: &str) -> Result<Vec<String>> {
 }
 
 pub fn detect_path(browser: &Browser, hint: &ProfileHint) -> Result<String> {
-    if browser.id.as_str() == hint.exe_path {
-        return Ok(browser
-            .command
-            .replace("%u", "") // remove one-URL placeholder
-            .replace("%U", "") // remove URLs placeholder
-            .trim_end() // remove trailing spaces
-            .to_string());
+    if WildMatch::new(&hint.exe_path).matches(&browser.id) {
+        return Ok(browser.command.clone());
     }
     Ok(String::new())
 }
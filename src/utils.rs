@@ -1,4 +1,7 @@
+use crate::error::*;
 use crate::*;
+use std::path::Path;
+use std::time::SystemTime;
 
 pub fn get_config_path() -> Result<PathBuf> {
     let mut exe_path = env::current_exe()?;
@@ -7,11 +10,79 @@ pub fn get_config_path() -> Result<PathBuf> {
     Ok(exe_path)
 }
 
+/// Directory of logs and state, $XDG_STATE_HOME/bro or %LOCALAPPDATA%\bro
+pub fn get_state_dir() -> Result<PathBuf> {
+    let mut dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ if cfg!(windows) => PathBuf::from(env::var("localappdata")?),
+        _ => {
+            let mut dir = PathBuf::from(env::var("HOME")?);
+            dir.extend([".local", "state"]);
+            dir
+        }
+    };
+    dir.push("bro");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Writes into a temporary file next to path, then renames it over path, so
+/// that readers never see a partially written file
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
 pub fn spawn_shell_command(exe: &str, args: &Vec<String>) -> Result<()> {
-    Command::new(exe).args(args).spawn()?;
+    Command::new(exe)
+        .args(args)
+        .spawn()
+        .map_err(|e| BroError::Launch {
+            command: exe.to_string(),
+            reason: e.to_string(),
+        })?;
     Ok(())
 }
 
+/// Parses a time like 2024-01-31T08:00:00Z, or a date like 2024-01-31 which
+/// is midnight UTC of that day
+pub fn parse_time(time: &str) -> Result<SystemTime> {
+    let timestamp = if time.len() == 10 {
+        format!("{}T00:00:00Z", time)
+    } else {
+        time.to_string()
+    };
+    humantime::parse_rfc3339_weak(&timestamp).map_err(|_| {
+        Error::msg(format!(
+            "invalid time {}, expected a date like 2024-01-31 or a time like 2024-01-31T08:00:00Z",
+            time
+        ))
+    })
+}
+
+/// Quotes an argument for POSIX shells when it contains anything besides
+/// characters that are safe unquoted
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
 pub fn is_64() -> bool {
-    return cfg!(target_pointer_width = "64");
+    cfg!(target_pointer_width = "64")
+}
+
+/// Checks whether the argument starts with a URL scheme like https:
+pub fn is_uri(arg: &str) -> bool {
+    Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*:")
+        .map(|r| r.is_match(arg))
+        .unwrap_or(false)
 }
@@ -106,7 +106,7 @@ pub fn register() -> Result<()> {
         .create_subkey("shell\\open\\command")
         .expect("Create BroHTTP command key failed");
     // set default value
-    let bro_cmd_val = format!("\"{}\" %1", exe_str);
+    let bro_cmd_val = format!("\"{}\" open \"%1\"", exe_str);
     bro_cmd_key
         .set_value("", &bro_cmd_val.as_str())
         .expect("Set BroHTTP command failed");
@@ -155,29 +155,53 @@ pub fn unregister() -> Result<()> {
 
 fn get_browser(key: &RegKey, name: &str) -> Result<Browser> {
     let subkey = key.open_subkey(name)?;
-    let mut ret = Browser {
+    let ret = Browser {
         id: String::from(name),
         name: subkey.get_value("")?,
         command: subkey.open_subkey("shell\\open\\command")?.get_value("")?,
+        args: Vec::new(),
         profiles: Vec::new(),
     };
-    get_profiles(&mut ret)?;
     Ok(ret)
 }
 
-pub fn available_browsers() -> Result<Vec<Browser>> {
+pub fn available_browsers(detection: &Detection) -> Result<DetectedBrowsers> {
     let mut ret: Vec<Browser> = Vec::new();
+    let mut warnings: Vec<DetectionWarning> = Vec::new();
     for hkey in [HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE] {
         let regkey = RegKey::predef(hkey);
         let browsers = regkey.open_subkey("Software\\Clients\\StartMenuInternet")?;
-        for browser in browsers.enum_keys().map(|x| x.unwrap()) {
-            let browser = get_browser(&browsers, &browser)?;
-            // println!("{:?}", browser);
-            ret.push(browser);
+        for browser in browsers.enum_keys() {
+            let browser = browser?;
+            match get_browser(&browsers, &browser) {
+                Err(e) => warnings.push(DetectionWarning {
+                    source: browser,
+                    message: format!("cannot read its registry key: {:#}", e),
+                }),
+                found => ret.push(found?),
+            }
         }
     }
+    warnings.extend(detect_profiles_parallel(&mut ret, |b| {
+        get_profiles(b, &detection.hints)
+    }));
+    Ok(DetectedBrowsers {
+        browsers: ret,
+        warnings,
+    })
+}
 
-    Ok(ret)
+/// Detects one browser again by its registry key, None when it is gone
+pub fn detect_browser(id: &str, detection: &Detection) -> Result<Option<Browser>> {
+    for hkey in [HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE] {
+        let regkey = RegKey::predef(hkey);
+        let browsers = regkey.open_subkey("Software\\Clients\\StartMenuInternet")?;
+        if let Some(mut browser) = get_browser(&browsers, id).ok() {
+            get_profiles(&mut browser, &detection.hints)?;
+            return Ok(Some(browser));
+        }
+    }
+    Ok(None)
 }
 
 pub fn expand_path(path: &str) -> Result<Vec<String>> {
@@ -212,10 +236,18 @@ pub fn is_wow64() -> bool {
 }
 
 pub fn detect_path(browser: &Browser, hint: &ProfileHint) -> Result<String> {
-    for path in expand_path(hint.exe_path)?.iter() {
-        if browser.command.contains(path) {
+    // patterns match the executable, which is quoted when it contains spaces
+    let program = match browser.command.strip_prefix('"') {
+        Some(command) => command.split('"').next().unwrap_or_default(),
+        None => browser.command.split(' ').next().unwrap_or_default(),
+    };
+    for path in expand_path(&hint.exe_path)?.iter() {
+        if browser.command.contains(path.as_str()) {
             return Ok(path.clone());
         }
+        if WildMatch::new(path).matches(program) {
+            return Ok(program.to_string());
+        }
     }
     Ok(String::new())
 }
@@ -0,0 +1,59 @@
+//! Programs of browser commands, which are checked before launching, and the
+//! URLs that they are launched with
+
+use bro::browser::{command_exists, launch_browser_command, launched_uris};
+use bro::types::Browser;
+
+#[test]
+fn commands_exist_only_as_the_program_that_is_spawned() {
+    assert!(command_exists("sh"));
+    assert!(command_exists("/bin/sh"));
+    // arguments are not part of the command, so spawning these would fail
+    assert!(!command_exists("sh -c true"));
+    assert!(!command_exists("/bin/echo --flag"));
+    assert!(!command_exists("env FOO=1 sh %u"));
+    assert!(!command_exists(r#""/bin/sh" -- "%1""#));
+    assert!(!command_exists("bro-missing-browser"));
+    assert!(!command_exists("/nonexistent/browser"));
+    assert!(!command_exists(""));
+}
+
+#[test]
+fn launched_urls_are_wrapped_for_containers() {
+    let browsers: Vec<Browser> = serde_json::from_str(
+        r#"[{
+            "id": "firefox",
+            "name": "Firefox",
+            "command": "/usr/bin/firefox",
+            "args": ["%u"],
+            "profiles": [
+                { "id": "default", "name": "default", "args": ["-P", "default"], "icon_path": "" },
+                {
+                    "id": "default/Work",
+                    "name": "Work (default)",
+                    "args": ["-P", "default"],
+                    "icon_path": "",
+                    "container": "Work"
+                }
+            ]
+        }]"#,
+    )
+    .unwrap();
+    let uris = [String::from("https://example.com/?q=1")];
+    for spec in [
+        "firefox",
+        "firefox:default",
+        "firefox:default/Work",
+        "chrome",
+    ] {
+        let urls = launched_uris(&browsers, spec, &uris);
+        match launch_browser_command(&browsers, spec, &uris) {
+            Err(_) => assert_eq!(urls, uris, "{}", spec),
+            cmd => assert_eq!(cmd.unwrap()[0].1.last(), urls.last(), "{}", spec),
+        }
+    }
+    assert_eq!(
+        launched_uris(&browsers, "firefox:default/Work", &uris),
+        ["ext+container:name=Work&url=https%3A%2F%2Fexample%2Ecom%2F%3Fq%3D1"]
+    );
+}
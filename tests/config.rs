@@ -0,0 +1,81 @@
+//! Editing rules of a config that is written by hand
+
+use bro::types::{Config, Rule};
+use serde_json::Value;
+use std::fs;
+
+const HAND_WRITTEN: &str = r#"{
+    "detected_browsers": [],
+    "custom_browsers": [
+        { "id": "firefox", "name": "Firefox", "command": "firefox" }
+    ],
+    "rules": [
+        { "matcher": "DOMAIN-SUFFIX", "pattern": "contoso.com", "browser": "firefox" },
+        { "matcher": "FINAL", "browser": "firefox" }
+    ]
+}"#;
+
+fn hand_written() -> Config {
+    serde_json::from_str(HAND_WRITTEN).unwrap()
+}
+
+#[test]
+fn saving_adds_no_default_sections() {
+    let path = std::env::temp_dir().join(format!("bro-config-{}.json", std::process::id()));
+    let mut config = hand_written();
+    config
+        .insert_rule(None, Rule::new("DOMAIN", "example.com", "firefox"))
+        .unwrap();
+    config.save(&path).unwrap();
+    let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let mut expected: Value = serde_json::from_str(HAND_WRITTEN).unwrap();
+    expected["rules"].as_array_mut().unwrap().insert(
+        1,
+        serde_json::json!({ "matcher": "DOMAIN", "pattern": "example.com", "browser": "firefox" }),
+    );
+    assert_eq!(saved, expected);
+}
+
+#[test]
+fn rule_that_has_expired_is_not_added() {
+    let mut config = hand_written();
+    let mut rule = Rule::new("DOMAIN", "example.com", "firefox");
+    rule.expires_at = Some(String::from("2020-01-31"));
+    let err = config.insert_rule(None, rule).unwrap_err();
+    assert!(format!("{:#}", err).contains("in the past"));
+    assert_eq!(config.rules.len(), 2);
+
+    let mut rule = Rule::new("DOMAIN", "example.com", "firefox");
+    rule.expires_at = Some(String::from("2999-01-31"));
+    assert_eq!(config.insert_rule(None, rule).unwrap(), 1);
+}
+
+/// Older versions kept the whole Exec line of browsers without a hint
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn commands_with_exec_arguments_are_split_when_loading() {
+    let path = std::env::temp_dir().join(format!("bro-legacy-{}.json", std::process::id()));
+    fs::write(
+        &path,
+        r#"{
+            "detected_browsers": [
+                { "id": "org.gnome.Epiphany.desktop", "name": "Web", "command": "epiphany %U" },
+                { "id": "firefox.desktop", "name": "Firefox", "command": "/usr/bin/firefox" }
+            ],
+            "custom_browsers": [],
+            "rules": [{ "matcher": "FINAL", "browser": "firefox.desktop" }]
+        }"#,
+    )
+    .unwrap();
+    let config = Config::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let epiphany = &config.detected_browsers[0];
+    assert_eq!(epiphany.command, "epiphany");
+    assert_eq!(epiphany.args, ["%U"]);
+    let firefox = &config.detected_browsers[1];
+    assert_eq!(firefox.command, "/usr/bin/firefox");
+    assert!(firefox.args.is_empty());
+}
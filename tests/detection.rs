@@ -0,0 +1,497 @@
+//! Profile detection of Linux browsers against fake profile directories in a
+//! fake HOME
+#![cfg(all(unix, not(target_os = "macos")))]
+
+use bro::browser::{
+    builtin_profile_hints, detect_profiles_parallel, get_profiles, merge_profile_hints,
+};
+use bro::desktop::DesktopEntryKeys;
+use bro::discovery::discover_browsers;
+use bro::platform::{desktop_entry_command, sandboxed_hints};
+use bro::types::{Browser, ProfileHint};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const CHROMIUM_FAMILY: &[(&str, &str, &str)] = &[
+    ("chromium.desktop", ".config/chromium", "--incognito"),
+    (
+        "chromium-browser.desktop",
+        ".config/chromium",
+        "--incognito",
+    ),
+    (
+        "google-chrome-unstable.desktop",
+        ".config/google-chrome-unstable",
+        "--incognito",
+    ),
+    (
+        "google-chrome-canary.desktop",
+        ".config/google-chrome-canary",
+        "--incognito",
+    ),
+    (
+        "microsoft-edge-beta.desktop",
+        ".config/microsoft-edge-beta",
+        "-inprivate",
+    ),
+    (
+        "microsoft-edge-dev.desktop",
+        ".config/microsoft-edge-dev",
+        "-inprivate",
+    ),
+    (
+        "brave-browser.desktop",
+        ".config/BraveSoftware/Brave-Browser",
+        "--incognito",
+    ),
+    ("thorium-browser.desktop", ".config/thorium", "--incognito"),
+];
+
+const GECKO_FAMILY: &[(&str, &str)] = &[
+    ("librewolf.desktop", ".librewolf"),
+    ("waterfox.desktop", ".waterfox"),
+    ("floorp.desktop", ".floorp"),
+];
+
+fn touch(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, "").unwrap();
+}
+
+fn touch_executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    touch(path);
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+fn fake_applications(dir: &Path) {
+    touch_executable(&dir.join("tor-browser/Browser/start-tor-browser"));
+    touch_executable(&dir.join("firefox-nightly/firefox"));
+    fs::write(
+        dir.join("firefox-nightly/application.ini"),
+        "[App]\nVendor=Mozilla\nName=Firefox\nRemotingName=firefox-nightly\nVersion=130.0a1\n",
+    )
+    .unwrap();
+    touch(&dir.join("firefox-nightly/browser/omni.ja"));
+    // a Mozilla based application that is not a browser
+    touch_executable(&dir.join("thunderbird/thunderbird"));
+    fs::write(
+        dir.join("thunderbird/application.ini"),
+        "[App]\nVendor=Mozilla\nName=Thunderbird\nVersion=128.0\n",
+    )
+    .unwrap();
+    // AppImages that are not ELF files, so only their file names are known
+    touch_executable(&dir.join("LibreWolf-124.0-1.x86_64.AppImage"));
+    touch_executable(&dir.join("LibreWolf-125.0-1.x86_64.AppImage"));
+    touch_executable(&dir.join("Obsidian-1.5.12.AppImage"));
+    // neither a tarball of a browser nor an AppImage
+    touch_executable(&dir.join("scripts/firefox"));
+}
+
+fn fake_chromium(user_data: &Path) {
+    touch(&user_data.join("Default/History"));
+    touch(&user_data.join("Profile 1/History"));
+    // not a profile, History is missing
+    fs::create_dir_all(user_data.join("Crashpad")).unwrap();
+    fs::write(
+        user_data.join("Local State"),
+        concat!(
+            r#"{"profile":{"info_cache":{"#,
+            r#""Profile 1":{"name":"Work","user_name":"alice@contoso.com","is_managed":1},"#,
+            // a consumer account has the NO_HOSTED_DOMAIN domain
+            r#""Default":{"name":"Personal","user_name":"alice@gmail.com","hosted_domain":"NO_HOSTED_DOMAIN"}"#,
+            r#"}}}"#
+        ),
+    )
+    .unwrap();
+}
+
+fn fake_gecko(root: &Path) {
+    touch(&root.join("a1.default-release/cookies.sqlite"));
+    touch(&root.join("b2.work/cookies.sqlite"));
+    fs::write(
+        root.join("profiles.ini"),
+        "[Profile1]\nName=work\nIsRelative=1\nPath=b2.work\n\n\
+         [Profile0]\nName=default-release\nIsRelative=1\nPath=a1.default-release\n\n\
+         [General]\nVersion=2\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("installs.ini"),
+        "[0123ABCD]\nDefault=a1.default-release\n",
+    )
+    .unwrap();
+}
+
+/// Fake HOME shared by all tests of this file, as HOME is process-wide
+fn fake_home() -> &'static Path {
+    static HOME: OnceLock<PathBuf> = OnceLock::new();
+    HOME.get_or_init(|| {
+        let home = std::env::temp_dir().join(format!("bro-detection-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        for (_, dir, _) in CHROMIUM_FAMILY {
+            fake_chromium(&home.join(dir));
+        }
+        for (_, dir) in GECKO_FAMILY {
+            fake_gecko(&home.join(dir));
+        }
+        fake_gecko(&home.join(".var/app/org.mozilla.firefox/.mozilla/firefox"));
+        fake_gecko(&home.join(".mozilla/firefox"));
+        fake_applications(&home.join("Applications"));
+        touch(&home.join(".config/opera/History"));
+        fake_chromium(&home.join("snap/chromium/common/chromium"));
+        touch(&home.join(".qutebrowser/personal/state"));
+        touch(&home.join(".qutebrowser/work/state"));
+        fs::create_dir_all(home.join(".qutebrowser/cache")).unwrap();
+        // profiles.ini that cannot be read
+        fs::create_dir_all(home.join(".broken/profiles.ini")).unwrap();
+        std::env::set_var("HOME", &home);
+        home
+    })
+}
+
+fn installed(id: &str) -> Browser {
+    Browser {
+        id: id.to_string(),
+        name: id.to_string(),
+        command: format!("/usr/bin/{}", id.trim_end_matches(".desktop")),
+        args: vec![String::from("%U")],
+        profiles: vec![],
+    }
+}
+
+fn detect_with(id: &str, hints: &[ProfileHint]) -> Browser {
+    fake_home();
+    let mut browser = installed(id);
+    get_profiles(&mut browser, hints).unwrap();
+    browser
+}
+
+fn detect(id: &str) -> Browser {
+    detect_with(id, &builtin_profile_hints())
+}
+
+fn profile_ids(browser: &Browser) -> Vec<&str> {
+    browser.profiles.iter().map(|p| p.id.as_str()).collect()
+}
+
+#[test]
+fn chromium_family_profiles_are_detected() {
+    for (id, _, private_arg) in CHROMIUM_FAMILY {
+        let browser = detect(id);
+        assert_eq!(
+            browser.command,
+            format!("/usr/bin/{}", id.trim_end_matches(".desktop")),
+            "{}",
+            id
+        );
+        let mut ids = profile_ids(&browser);
+        ids[1..].sort();
+        assert_eq!(ids, ["__PRIVATE__", "Default", "Profile 1"], "{}", id);
+        assert_eq!(browser.profiles[0].args, [*private_arg], "{}", id);
+
+        let work = browser
+            .profiles
+            .iter()
+            .find(|p| p.id == "Profile 1")
+            .unwrap();
+        assert_eq!(work.name, "Work", "{}", id);
+        assert_eq!(work.email, "alice@contoso.com", "{}", id);
+        assert!(work.managed, "{}", id);
+        assert_eq!(work.args, ["--profile-directory=Profile 1"], "{}", id);
+
+        let personal = browser.profiles.iter().find(|p| p.id == "Default").unwrap();
+        assert_eq!(personal.email, "alice@gmail.com", "{}", id);
+        assert!(!personal.managed, "{}", id);
+    }
+}
+
+#[test]
+fn gecko_family_profiles_are_read_from_profiles_ini() {
+    for (id, dir) in GECKO_FAMILY {
+        let browser = detect(id);
+        assert_eq!(
+            profile_ids(&browser),
+            ["__PRIVATE__", "default-release", "work"],
+            "{}",
+            id
+        );
+        assert_eq!(browser.profiles[0].args, ["-private-window"], "{}", id);
+
+        let default = &browser.profiles[1];
+        assert!(default.default, "{}", id);
+        assert_eq!(default.args, ["-P", "default-release"], "{}", id);
+        assert_eq!(
+            Path::new(&default.path),
+            fake_home().join(dir).join("a1.default-release"),
+            "{}",
+            id
+        );
+    }
+}
+
+#[test]
+fn default_profile_is_taken_from_install_whose_profile_is_registered() {
+    let root = fake_home().join(".twoinstalls");
+    touch(&root.join("a1.default-release/cookies.sqlite"));
+    touch(&root.join("b2.work/cookies.sqlite"));
+    touch(&root.join("c3.esr/cookies.sqlite"));
+    fs::write(
+        root.join("profiles.ini"),
+        "[Profile0]\nName=default-release\nIsRelative=1\nPath=a1.default-release\n\n\
+         [Profile1]\nName=work\nIsRelative=1\nPath=b2.work\nDefault=1\n\n\
+         [Profile2]\nName=esr\nIsRelative=1\nPath=c3.esr\n\n\
+         [Install0123ABCD]\nDefault=z9.removed\nLocked=1\n\n\
+         [Install4567CDEF]\nDefault=c3.esr\nLocked=1\n\n\
+         [Install89ABEF01]\nDefault=b2.work\nLocked=1\n\n\
+         [General]\nVersion=2\n",
+    )
+    .unwrap();
+    let hints: Vec<ProfileHint> = serde_json::from_str(
+        r#"[{
+            "exe_path": "twoinstalls.desktop",
+            "profiles_path": "~/.twoinstalls",
+            "kind": "gecko"
+        }]"#,
+    )
+    .unwrap();
+    let browser = detect_with("twoinstalls.desktop", &hints);
+    let defaults: Vec<&str> = browser
+        .profiles
+        .iter()
+        .filter(|p| p.default)
+        .map(|p| p.id.as_str())
+        .collect();
+    assert_eq!(defaults, ["work"]);
+    assert_eq!(browser.profiles[0].id, "work");
+}
+
+#[test]
+fn opera_profiles_directory_is_its_only_profile() {
+    let browser = detect("opera.desktop");
+    assert_eq!(profile_ids(&browser), ["__PRIVATE__", "Default"]);
+    assert_eq!(browser.profiles[0].args, ["--private"]);
+    let default = &browser.profiles[1];
+    assert!(default.default);
+    assert!(default.args.is_empty());
+    assert_eq!(Path::new(&default.path), fake_home().join(".config/opera"));
+}
+
+#[test]
+fn browser_without_profiles_directory_only_has_private_profile() {
+    let browser = detect("vivaldi-stable.desktop");
+    assert_eq!(profile_ids(&browser), ["__PRIVATE__"]);
+    assert_eq!(browser.profiles[0].args, ["--incognito"]);
+}
+
+#[test]
+fn unknown_browser_has_no_profiles() {
+    let browser = detect("dillo.desktop");
+    assert!(browser.profiles.is_empty());
+    assert_eq!(browser.command, "/usr/bin/dillo");
+}
+
+#[test]
+fn user_defined_hint_detects_generic_profiles() {
+    let hints: Vec<ProfileHint> = serde_json::from_str(
+        r#"[{
+            "exe_path": "org.qutebrowser.*.desktop",
+            "profiles_path": "~/.qutebrowser",
+            "marker": "state",
+            "profile_args": ["--basedir", "{path}"],
+            "private_arg": "--target=private-window"
+        }]"#,
+    )
+    .unwrap();
+    let browser = detect_with(
+        "org.qutebrowser.qutebrowser.desktop",
+        &merge_profile_hints(&hints),
+    );
+    let mut ids = profile_ids(&browser);
+    ids[1..].sort();
+    assert_eq!(ids, ["__PRIVATE__", "personal", "work"]);
+    assert_eq!(browser.profiles[0].name, "Private window");
+    let work = browser.profiles.iter().find(|p| p.id == "work").unwrap();
+    assert_eq!(
+        work.args,
+        [
+            "--basedir",
+            &fake_home().join(".qutebrowser/work").to_string_lossy()
+        ]
+    );
+}
+
+#[test]
+fn user_defined_hint_overrides_builtin_hint() {
+    let hints: Vec<ProfileHint> = serde_json::from_str(
+        r#"[{
+            "exe_path": "chromium.desktop",
+            "profiles_path": "~/.config/chromium",
+            "kind": "chromium",
+            "profile_args": ["--profile-directory={id}", "--class={name}"]
+        }]"#,
+    )
+    .unwrap();
+    let browser = detect_with("chromium.desktop", &merge_profile_hints(&hints));
+    let mut ids = profile_ids(&browser);
+    ids.sort();
+    assert_eq!(ids, ["Default", "Profile 1"]);
+    let work = browser
+        .profiles
+        .iter()
+        .find(|p| p.id == "Profile 1")
+        .unwrap();
+    assert_eq!(work.args, ["--profile-directory=Profile 1", "--class=Work"]);
+}
+
+#[test]
+fn flatpak_and_snap_profiles_are_detected_in_the_sandbox() {
+    let hints = sandboxed_hints(&builtin_profile_hints());
+
+    let firefox = detect_with("org.mozilla.firefox.desktop", &hints);
+    assert_eq!(
+        profile_ids(&firefox),
+        ["__PRIVATE__", "default-release", "work"]
+    );
+    assert_eq!(
+        Path::new(&firefox.profiles[1].path),
+        fake_home().join(".var/app/org.mozilla.firefox/.mozilla/firefox/a1.default-release")
+    );
+
+    let chromium = detect_with("chromium_chromium.desktop", &hints);
+    let work = chromium
+        .profiles
+        .iter()
+        .find(|p| p.id == "Profile 1")
+        .unwrap();
+    assert_eq!(work.name, "Work");
+    assert_eq!(
+        Path::new(&work.path),
+        fake_home().join("snap/chromium/common/chromium/Profile 1")
+    );
+}
+
+#[test]
+fn flatpak_and_snap_commands_are_split_into_program_and_arguments() {
+    let keys = DesktopEntryKeys::default();
+    let command = |exec: &str| {
+        let (program, args) = desktop_entry_command(exec, &keys).unwrap();
+        let mut command = vec![program];
+        command.extend(args);
+        command
+    };
+    assert_eq!(
+        command(
+            "/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=firefox \
+             --file-forwarding org.mozilla.firefox @@u %u @@"
+        ),
+        [
+            "/usr/bin/flatpak",
+            "run",
+            "--branch=stable",
+            "--arch=x86_64",
+            "--command=firefox",
+            "org.mozilla.firefox",
+            "%u"
+        ]
+    );
+    assert_eq!(
+        command(
+            "env BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/applications/chromium_chromium.desktop \
+             /snap/bin/chromium %U"
+        ),
+        ["/snap/bin/chromium", "%U"]
+    );
+    assert_eq!(
+        command("env MOZ_ENABLE_WAYLAND=1 firefox %u"),
+        ["env", "MOZ_ENABLE_WAYLAND=1", "firefox", "%u"]
+    );
+}
+
+#[test]
+fn browsers_without_desktop_entries_are_discovered_in_search_directories() {
+    fake_home();
+    let search_dirs = [String::from("~/Applications"), String::from("~/missing")];
+    let detected = discover_browsers(&search_dirs, &builtin_profile_hints());
+    let browsers = &detected.browsers;
+    let ids: Vec<&str> = browsers.iter().map(|b| b.id.as_str()).collect();
+    assert_eq!(
+        ids,
+        ["librewolf.AppImage", "firefox-nightly", "tor-browser"]
+    );
+    let applications = fake_home().join("Applications");
+
+    // an AppImage of another version of the same browser
+    assert_eq!(detected.warnings.len(), 1);
+    assert_eq!(
+        Path::new(&detected.warnings[0].source),
+        applications.join("LibreWolf-125.0-1.x86_64.AppImage")
+    );
+
+    let librewolf = &browsers[0];
+    assert_eq!(librewolf.name, "LibreWolf");
+    assert_eq!(
+        Path::new(&librewolf.command),
+        applications.join("LibreWolf-124.0-1.x86_64.AppImage")
+    );
+    assert_eq!(
+        profile_ids(librewolf),
+        ["__PRIVATE__", "default-release", "work"]
+    );
+
+    let nightly = &browsers[1];
+    assert_eq!(nightly.name, "Firefox 130.0a1");
+    assert_eq!(
+        Path::new(&nightly.command),
+        applications.join("firefox-nightly/firefox")
+    );
+    assert_eq!(
+        profile_ids(nightly),
+        ["__PRIVATE__", "default-release", "work"]
+    );
+
+    let tor = &browsers[2];
+    assert_eq!(tor.name, "Tor Browser");
+    assert_eq!(
+        Path::new(&tor.command),
+        applications.join("tor-browser/Browser/start-tor-browser")
+    );
+    assert!(tor.profiles.is_empty());
+}
+
+#[test]
+fn broken_browsers_are_kept_without_profiles() {
+    fake_home();
+    let hints: Vec<ProfileHint> = serde_json::from_str(
+        r#"[{
+            "exe_path": "broken.desktop",
+            "profiles_path": "~/.broken",
+            "kind": "gecko",
+            "private_arg": "-private-window"
+        }]"#,
+    )
+    .unwrap();
+    let hints = merge_profile_hints(&hints);
+    let mut browsers = vec![
+        installed("broken.desktop"),
+        installed("panicking.desktop"),
+        installed("librewolf.desktop"),
+    ];
+    let warnings = detect_profiles_parallel(&mut browsers, |browser| {
+        if browser.id == "panicking.desktop" {
+            panic!("profile detection of {} failed", browser.id);
+        }
+        get_profiles(browser, &hints)
+    });
+
+    let sources: Vec<&str> = warnings.iter().map(|w| w.source.as_str()).collect();
+    assert_eq!(sources, ["broken.desktop", "panicking.desktop"]);
+    assert!(warnings[0].message.contains("profiles.ini"));
+    assert!(browsers[0].profiles.is_empty());
+    assert!(browsers[1].profiles.is_empty());
+    assert_eq!(
+        profile_ids(&browsers[2]),
+        ["__PRIVATE__", "default-release", "work"]
+    );
+}
@@ -0,0 +1,53 @@
+//! Problems counted by `bro doctor`, which decide its exit code
+
+use bro::doctor::*;
+use bro::error::{exit_code, BroError, EXIT_PROBLEMS};
+
+fn diagnosis(default_browser: DefaultBrowserCheck) -> Diagnosis {
+    Diagnosis {
+        config: ConfigCheck {
+            path: String::from("bro.json"),
+            exists: true,
+            error: None,
+        },
+        default_browser,
+        desktop_entry: None,
+        browsers: vec![],
+        detection: DetectionCheck {
+            browsers: 0,
+            duration_ms: 0,
+            error: None,
+            warnings: vec![],
+        },
+    }
+}
+
+#[test]
+fn unknown_default_browser_is_one_problem() {
+    let unknown = diagnosis(DefaultBrowserCheck {
+        handler: None,
+        is_bro: false,
+        error: Some(String::from("xdg-settings is not installed")),
+    });
+    assert_eq!(unknown.problems(), 1);
+
+    let other = diagnosis(DefaultBrowserCheck {
+        handler: Some(String::from("firefox.desktop")),
+        is_bro: false,
+        error: None,
+    });
+    assert_eq!(other.problems(), 1);
+
+    let bro = diagnosis(DefaultBrowserCheck {
+        handler: Some(String::from("bro.desktop")),
+        is_bro: true,
+        error: None,
+    });
+    assert_eq!(bro.problems(), 0);
+}
+
+#[test]
+fn problems_have_their_own_exit_code() {
+    let err = anyhow::Error::from(BroError::ProblemsFound(2));
+    assert_eq!(exit_code(&err), EXIT_PROBLEMS);
+}
@@ -0,0 +1,264 @@
+//! Exec values of real-world desktop files, as they are written in the files
+
+use bro::desktop::{expand_exec_urls, parse_exec, split_exec, DesktopEntryKeys};
+
+const KEYS: DesktopEntryKeys = DesktopEntryKeys {
+    name: "Web Browser",
+    icon: "web-browser",
+    path: "/usr/share/applications/browser.desktop",
+};
+
+/// Desktop file, Exec value and the expected program and arguments
+const CORPUS: &[(&str, &str, &[&str])] = &[
+    ("firefox.desktop", "firefox %u", &["firefox", "%u"]),
+    (
+        "firefox.desktop (Fedora)",
+        "firefox --name firefox %U",
+        &["firefox", "--name", "firefox", "%U"],
+    ),
+    (
+        "google-chrome.desktop",
+        "/usr/bin/google-chrome-stable %U",
+        &["/usr/bin/google-chrome-stable", "%U"],
+    ),
+    (
+        "chromium.desktop (Arch)",
+        "/usr/bin/chromium %U",
+        &["/usr/bin/chromium", "%U"],
+    ),
+    (
+        "microsoft-edge.desktop",
+        "/usr/bin/microsoft-edge-stable %U",
+        &["/usr/bin/microsoft-edge-stable", "%U"],
+    ),
+    (
+        "brave-browser.desktop",
+        "/usr/bin/brave-browser-stable %U",
+        &["/usr/bin/brave-browser-stable", "%U"],
+    ),
+    (
+        "vivaldi-stable.desktop",
+        "/usr/bin/vivaldi-stable %U",
+        &["/usr/bin/vivaldi-stable", "%U"],
+    ),
+    ("opera.desktop", "opera %U", &["opera", "%U"]),
+    (
+        "org.gnome.Epiphany.desktop",
+        "epiphany %U",
+        &["epiphany", "%U"],
+    ),
+    (
+        "org.qutebrowser.qutebrowser.desktop",
+        "qutebrowser --untrusted-args %u",
+        &["qutebrowser", "--untrusted-args", "%u"],
+    ),
+    ("org.kde.falkon.desktop", "falkon %u", &["falkon", "%u"]),
+    (
+        "org.mozilla.firefox.desktop (Flatpak)",
+        "/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=firefox \
+         --file-forwarding org.mozilla.firefox @@u %u @@",
+        &[
+            "/usr/bin/flatpak",
+            "run",
+            "--branch=stable",
+            "--arch=x86_64",
+            "--command=firefox",
+            "--file-forwarding",
+            "org.mozilla.firefox",
+            "@@u",
+            "%u",
+            "@@",
+        ],
+    ),
+    (
+        "chromium_chromium.desktop (Snap)",
+        "env BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/applications/chromium_chromium.desktop \
+         /snap/bin/chromium %U",
+        &[
+            "env",
+            "BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/applications/chromium_chromium.desktop",
+            "/snap/bin/chromium",
+            "%U",
+        ],
+    ),
+    (
+        "firefox-wayland.desktop",
+        "env MOZ_ENABLE_WAYLAND=1 firefox %u",
+        &["env", "MOZ_ENABLE_WAYLAND=1", "firefox", "%u"],
+    ),
+    (
+        "start-tor-browser.desktop",
+        r#"sh -c '"/home/alice/tor-browser/Browser/start-tor-browser" --detach || ([ !  -x "/home/alice/tor-browser/Browser/start-tor-browser" ] && "$(dirname "$*")"/Browser/start-tor-browser --detach)' dummy %k"#,
+        &[
+            "sh",
+            "-c",
+            r#""/home/alice/tor-browser/Browser/start-tor-browser" --detach || ([ !  -x "/home/alice/tor-browser/Browser/start-tor-browser" ] && "$(dirname "$*")"/Browser/start-tor-browser --detach)"#,
+            "dummy",
+            "/usr/share/applications/browser.desktop",
+        ],
+    ),
+    (
+        "quoted program path",
+        r#""/opt/Web Browser/browser" --class="Web Browser" %U"#,
+        &["/opt/Web Browser/browser", "--class=Web Browser", "%U"],
+    ),
+    (
+        "escaped quotes in a quoted argument",
+        r#"sh -c "echo \\"$HOME\\" \\$PATH" %u"#,
+        &["sh", "-c", r#"echo "$HOME" $PATH"#, "%u"],
+    ),
+    (
+        "string escapes of desktop files",
+        r"browser\s--flag\tvalue %U",
+        &["browser", "--flag", "value", "%U"],
+    ),
+    (
+        "empty quoted argument",
+        r#"browser "" %U"#,
+        &["browser", "", "%U"],
+    ),
+    (
+        "icon and name field codes",
+        "browser %i --class %c %U",
+        &[
+            "browser",
+            "--icon",
+            "web-browser",
+            "--class",
+            "Web Browser",
+            "%U",
+        ],
+    ),
+    (
+        "deprecated field codes",
+        "browser %d %D %n %N %v %m %U",
+        &["browser", "%U"],
+    ),
+    (
+        "literal percent sign",
+        "browser --zoom=100%% %U",
+        &["browser", "--zoom=100%%", "%U"],
+    ),
+    (
+        "file field code",
+        "/usr/lib/browser/browser -file %F",
+        &["/usr/lib/browser/browser", "-file", "%F"],
+    ),
+];
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn corpus_exec_values_are_parsed() {
+    for (file, exec, expected) in CORPUS {
+        assert_eq!(parse_exec(exec, &KEYS).unwrap(), *expected, "{}", file);
+    }
+}
+
+#[test]
+fn icon_field_code_is_removed_without_icon() {
+    let keys = DesktopEntryKeys { icon: "", ..KEYS };
+    assert_eq!(
+        parse_exec("browser %i %U", &keys).unwrap(),
+        ["browser", "%U"]
+    );
+}
+
+#[test]
+fn invalid_exec_values_are_rejected() {
+    assert!(split_exec(r#"browser "--unterminated %U"#).is_err());
+    assert!(split_exec("browser '--unterminated %U").is_err());
+    assert!(split_exec("   ").is_err());
+    assert!(parse_exec("%d %m", &KEYS).is_err());
+}
+
+#[test]
+fn urls_are_inserted_at_the_field_code_after_profile_arguments() {
+    let urls = strings(&["https://a.example/", "https://b.example/"]);
+    let profile = strings(&["-P", "work"]);
+    assert_eq!(
+        expand_exec_urls(&strings(&["--name", "firefox", "%U"]), &profile, &urls),
+        [[
+            "--name",
+            "firefox",
+            "-P",
+            "work",
+            "https://a.example/",
+            "https://b.example/"
+        ]]
+    );
+}
+
+#[test]
+fn single_url_field_codes_launch_once_per_url() {
+    let urls = strings(&["https://a.example/", "https://b.example/"]);
+    assert_eq!(
+        expand_exec_urls(&strings(&["%u", "--new-window"]), &[], &urls),
+        [
+            ["https://a.example/", "--new-window"],
+            ["https://b.example/", "--new-window"]
+        ]
+    );
+    // qutebrowser only takes URLs after --untrusted-args
+    let (_, exec, _) = CORPUS
+        .iter()
+        .find(|(file, _, _)| *file == "org.qutebrowser.qutebrowser.desktop")
+        .unwrap();
+    let args = parse_exec(exec, &KEYS).unwrap();
+    let profile = strings(&["--basedir", "/home/alice/.qutebrowser/work"]);
+    assert_eq!(
+        expand_exec_urls(&args[1..], &profile, &urls),
+        [
+            [
+                "--basedir",
+                "/home/alice/.qutebrowser/work",
+                "--untrusted-args",
+                "https://a.example/"
+            ],
+            [
+                "--basedir",
+                "/home/alice/.qutebrowser/work",
+                "--untrusted-args",
+                "https://b.example/"
+            ]
+        ]
+    );
+}
+
+#[test]
+fn urls_are_appended_without_field_code() {
+    let urls = strings(&["https://a.example/"]);
+    assert_eq!(
+        expand_exec_urls(
+            &strings(&["--zoom=100%%"]),
+            &strings(&["--incognito"]),
+            &urls
+        ),
+        [["--zoom=100%", "--incognito", "https://a.example/"]]
+    );
+    assert_eq!(expand_exec_urls(&[], &[], &urls), [["https://a.example/"]]);
+}
+
+#[test]
+fn embedded_field_code_is_repeated_for_every_url() {
+    let urls = strings(&["https://a.example/", "https://b.example/"]);
+    assert_eq!(
+        expand_exec_urls(&strings(&["--app=%U"]), &[], &urls),
+        [["--app=https://a.example/", "--app=https://b.example/"]]
+    );
+    assert_eq!(
+        expand_exec_urls(&strings(&["--app=%u"]), &[], &urls),
+        [["--app=https://a.example/"], ["--app=https://b.example/"]]
+    );
+}
+
+#[test]
+fn file_field_codes_take_local_paths() {
+    let urls = strings(&["file:///home/alice/My%20Page.html", "https://a.example/"]);
+    assert_eq!(
+        expand_exec_urls(&strings(&["-file", "%F"]), &[], &urls),
+        [["-file", "/home/alice/My Page.html", "https://a.example/"]]
+    );
+}
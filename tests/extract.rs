@@ -0,0 +1,25 @@
+//! URLs found in free text by `bro extract`
+
+use bro::extract::extract_uris;
+
+#[test]
+fn urls_in_angle_brackets_keep_their_whitespace_out() {
+    let text = "See <https://example.com/a b> and <URL:https://example.com/\n  wrapped/path>,\n\
+                or < www.example.org/c >, but not <alice@example.com> or <br>.";
+    assert_eq!(
+        extract_uris(text),
+        [
+            "https://example.com/ab",
+            "https://example.com/wrapped/path",
+            "https://www.example.org/c"
+        ]
+    );
+}
+
+#[test]
+fn unclosed_angle_bracket_falls_back_to_the_url_itself() {
+    assert_eq!(
+        extract_uris("1 < 2, see https://example.com/x."),
+        ["https://example.com/x"]
+    );
+}
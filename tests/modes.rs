@@ -0,0 +1,56 @@
+//! Precedence of the rules of the active mode over the base rules
+
+use bro::rule::match_rule_by;
+use bro::types::{Config, MatchStrategy};
+
+const CONFIG: &str = r#"{
+    "detected_browsers": [],
+    "custom_browsers": [],
+    "rules": [
+        { "matcher": "DOMAIN-SUFFIX", "pattern": "contoso.com", "browser": "work" },
+        { "matcher": "FINAL", "browser": "default" }
+    ],
+    "modes": {
+        "home": [ { "matcher": "FINAL", "browser": "personal" } ],
+        "presenting": [ { "matcher": "WILDCARD", "pattern": "*", "browser": "private" } ]
+    }
+}"#;
+
+fn route(mode: Option<&str>, uri: &str) -> Vec<String> {
+    let config: Config = serde_json::from_str(CONFIG).unwrap();
+    let compiled = config.compile_active_rules(mode).unwrap();
+    [MatchStrategy::FirstMatch, MatchStrategy::MostSpecific]
+        .into_iter()
+        .map(|strategy| {
+            match_rule_by(&compiled, uri, strategy)
+                .unwrap()
+                .browser
+                .clone()
+        })
+        .collect()
+}
+
+#[test]
+fn final_rule_of_mode_replaces_base_final_rule() {
+    assert_eq!(
+        route(Some("home"), "https://www.contoso.com/"),
+        ["work", "work"]
+    );
+    assert_eq!(
+        route(Some("home"), "https://example.com/"),
+        ["personal", "personal"]
+    );
+    assert_eq!(route(None, "https://example.com/"), ["default", "default"]);
+}
+
+#[test]
+fn other_rules_of_mode_are_matched_before_base_rules() {
+    assert_eq!(
+        route(Some("presenting"), "https://www.contoso.com/"),
+        ["private", "private"]
+    );
+    assert_eq!(
+        route(Some("presenting"), "https://example.com/"),
+        ["private", "private"]
+    );
+}
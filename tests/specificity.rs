@@ -0,0 +1,32 @@
+//! Ranking of matching rules under the most-specific match strategy
+
+use bro::rule::{compile_rules, match_rule_by};
+use bro::types::{MatchStrategy, Rule};
+
+fn most_specific(rules: &str, uri: &str) -> String {
+    let rules: Vec<Rule> = serde_json::from_str(rules).unwrap();
+    let compiled = compile_rules(&rules).unwrap();
+    match_rule_by(&compiled, uri, MatchStrategy::MostSpecific)
+        .unwrap()
+        .browser
+        .clone()
+}
+
+#[test]
+fn earlier_rule_wins_over_longer_pattern_in_same_tier() {
+    let rules = r#"[
+        { "matcher": "WILDCARD", "pattern": "*example*", "browser": "first" },
+        { "matcher": "REGEX", "pattern": "^https://(www\\.)?example\\.com", "browser": "second" }
+    ]"#;
+    assert_eq!(most_specific(rules, "https://www.example.com/"), "first");
+}
+
+#[test]
+fn domain_suffix_with_more_labels_wins() {
+    let rules = r#"[
+        { "matcher": "DOMAIN-SUFFIX", "pattern": "example.com", "browser": "short" },
+        { "matcher": "DOMAIN-SUFFIX", "pattern": "docs.example.com", "browser": "long" }
+    ]"#;
+    assert_eq!(most_specific(rules, "https://api.docs.example.com/"), "long");
+    assert_eq!(most_specific(rules, "https://www.example.com/"), "short");
+}